@@ -1,6 +1,10 @@
 
+use std::fs;
+use std::thread;
+
 use docopt::Docopt;
 
+use crate::errors::{AppError, AppResult};
 use crate::types::*;
 
 
@@ -9,22 +13,64 @@ const USAGE: &str = "
 not q
 
 Usage:
-  nq [options] <csv> [-- <sqlite-options>...]
+  nq [options] [--attach DATABASE]... [--type SPEC]... [--rename SPEC]... [--index COLUMNS]... <csv> [-- <sqlite-options>...]
   nq (-h | --help)
   nq --version
 
+  <csv> may be ARCHIVE!PATTERN to load the members of a zip, tar or tar.gz ARCHIVE matching PATTERN.
+  <csv> may be a DIRECTORY or a GLOB pattern to load the matching files into one table, with the source and line columns.
+  Column types in the header like `age:int` override the guessed ones. TYPE is int, real or text.
+
 Options:
   -c CACHE      Cache *.sqlite
   -d DELIMITER  Format: Delimter for CSV
-  -e ENCODING   CSV character encoding: https://encoding.spec.whatwg.org/#concept-encoding-get
+  -e ENCODING   CSV character encoding (guessed if omitted): https://encoding.spec.whatwg.org/#concept-encoding-get
+  -f            Format: Fixed-width columns
   -g LINES      Guess column types
+  -H            Format: HTML tables
   -j            Format: JSON
+  -k            Format: logfmt (key=value)
   -l            Format: LTSV
+  -m            Format: Markdown tables
   -r FORMAT     Format: Regular expression
   -n            No header line
+  -p            Format: Parquet or Arrow IPC
   -q SQL        SQL
   -s            Format: Simple (white spaces split text)
+  -t            Format: TOML (array of tables)
+  -x            Format: Spreadsheet (xlsx, xls, ods)
+  -y            Format: YAML
   -R            Force refresh cache
+  --attach DATABASE              Attach SQLite DATABASE ([NAME=]PATH) to the session
+  --columns COLUMNS              Load only COLUMNS (comma separated) in this order
+  --comment PREFIX               CSV: Skip lines starting with PREFIX
+  --decode-errors POLICY         Invalid bytes: replace, strict (fail at the position) or ignore [default: replace]
+  --flatten-delimiter DELIMITER  JSON/YAML/TOML/XML: Delimiter to join nested keys [default: _]
+  --escape CHAR                  CSV: Escape character in quoted fields
+  --flatten-depth DEPTH          JSON/YAML/TOML/XML: Store objects nested deeper than DEPTH as JSON text
+  --fts COLUMNS                  Create the full-text search table n_fts (FTS5) over COLUMNS (comma separated) of n, reused by the later runs without it
+  --header-line LINE             CSV: The header (or the first row with -n) is on LINE, the lines before are skipped [default: 1]
+  --ragged MODE                  CSV: Rows with a different number of fields: strict, pad, truncate, overflow or reject [default: strict]
+  --primary-key COLUMNS          Create n with the primary key COLUMNS (comma separated), reused by the later runs without it
+  --quote CHAR                   CSV: Quote character [default: \"]
+  --quiet                        Do not report the loading progress
+  --rename SPEC                  Rename a column (SPEC: OLD=NEW)
+  --report FORMAT                Print the load report (stored in meta as report) to stderr in FORMAT: json
+  --ranges RANGES                Fixed-width: Column ranges (e.g. 1-8,9-20,21-)
+  --index COLUMNS                Create an index on COLUMNS (comma separated) of n, reused by the later runs without it
+  --jobs JOBS                    JSON/logfmt/LTSV/Regex: Parse on JOBS threads (the number of CPUs if omitted)
+  --naming NAMING                Names of the columns without header: alpha (a .. z, aa, ab ..) or numbered (c1, c2 ..) [default: alpha]
+  --member-tables                Archive: Load each member into its own table
+  --schema FILE                  Column types and names from FILE (`COLUMN TYPE` and `OLD=NEW` lines)
+  --sheet SHEET                  Spreadsheet: Load only SHEET
+  --snake-case                   Convert column names to snake_case
+  --skip-footer LINES            CSV: Skip the last LINES lines [default: 0]
+  --table-index INDEX            Markdown/HTML: Load only the INDEXth (1-based) table
+  --toml-table KEY               TOML: Dotted key of the array of tables
+  --type SPEC                    Set the type of a column (SPEC: COLUMN=TYPE)
+  --trim                         CSV: Trim white spaces around fields
+  --where FILTER                 Load only the rows matching FILTER: SQL expression, or /REGEX/ searched in the fields
+  --xml-record ELEMENT           Format: XML, ELEMENT is the repeating record element
   -h --help     Show this screen.
   --version     Show version.
 ";
@@ -32,18 +78,56 @@ Options:
 #[derive(Debug, Deserialize)]#[allow(non_snake_case)]
 pub struct AppOptions {
     pub arg_csv: String,
+    pub flag_attach: Vec<String>,
     pub flag_c: Option<String>,
+    pub flag_columns: Option<String>,
+    pub flag_comment: Option<String>,
     pub flag_d: Option<char>,
+    pub flag_decode_errors: DecodePolicy,
     pub flag_e: Option<String>,
+    pub flag_escape: Option<char>,
+    pub flag_f: bool,
+    pub flag_flatten_delimiter: String,
+    pub flag_flatten_depth: Option<usize>,
+    pub flag_fts: Option<String>,
     pub flag_g: Option<usize>,
     pub flag_r: Option<String>,
+    pub flag_H: bool,
+    pub flag_index: Vec<String>,
+    pub flag_header_line: usize,
     pub flag_j: bool,
+    pub flag_jobs: Option<usize>,
+    pub flag_k: bool,
     pub flag_l: bool,
+    pub flag_member_tables: bool,
+    pub flag_naming: Naming,
+    pub flag_m: bool,
     pub flag_n: bool,
+    pub flag_p: bool,
+    pub flag_primary_key: Option<String>,
     pub flag_q: Option<String>,
+    pub flag_quiet: bool,
+    pub flag_quote: char,
+    pub flag_ragged: Ragged,
     pub flag_s: bool,
+    pub flag_sheet: Option<String>,
+    pub flag_skip_footer: usize,
+    pub flag_snake_case: bool,
+    pub flag_t: bool,
+    pub flag_table_index: Option<usize>,
+    pub flag_toml_table: Option<String>,
+    pub flag_trim: bool,
+    pub flag_y: bool,
     pub flag_version: bool,
+    pub flag_where: Option<String>,
+    pub flag_x: bool,
+    pub flag_xml_record: Option<String>,
     pub flag_R: bool,
+    pub flag_ranges: Option<String>,
+    pub flag_rename: Vec<String>,
+    pub flag_report: Option<ReportFormat>,
+    pub flag_schema: Option<String>,
+    pub flag_type: Vec<String>,
     pub arg_sqlite_options: Vec<String>,
 }
 
@@ -55,14 +139,96 @@ impl AppOptions {
     pub fn format(&self) -> Format {
         if self.flag_l {
             Format::Ltsv
+        } else if self.flag_m {
+            Format::Markdown(self.flag_table_index)
+        } else if self.flag_H {
+            Format::Html(self.flag_table_index)
+        } else if self.flag_k {
+            Format::Logfmt
         } else if self.flag_j {
-            Format::Json
+            Format::Json(self.flatten())
+        } else if self.flag_y {
+            Format::Yaml(self.flatten())
+        } else if self.flag_f || self.flag_ranges.is_some() {
+            Format::FixedWidth(self.flag_ranges.clone())
+        } else if self.flag_p {
+            Format::Columnar
+        } else if self.flag_x || self.flag_sheet.is_some() {
+            Format::Spreadsheet(self.flag_sheet.clone())
+        } else if self.flag_t {
+            Format::Toml(self.flatten(), self.flag_toml_table.clone())
         } else if self.flag_s {
             Format::Simple
+        } else if let Some(ref record) = self.flag_xml_record {
+            Format::Xml(self.flatten(), record.to_owned())
         } else if let Some(ref format) = self.flag_r {
             Format::Regex(format.to_owned())
         } else {
-            Format::Csv(self.flag_d.map(|it| it as u8))
+            Format::Csv(self.dialect())
         }
     }
+
+    pub fn decoding(&self) -> Decoding {
+        Decoding { encoding: self.flag_e.clone(), policy: self.flag_decode_errors }
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.flag_jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |it| it.get())).max(1)
+    }
+
+    /// `--schema` file, then `--type` and `--rename`
+    pub fn schema(&self) -> AppResult<Schema> {
+        let mut result = Schema::default();
+
+        if let Some(ref path) = self.flag_schema {
+            for line in fs::read_to_string(path)?.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match line.rsplit_once(char::is_whitespace) {
+                    Some((column, t)) if !line.contains('=') || Type::parse(t).is_some() =>
+                        result.types.push((column.trim().to_owned(), parse_type(t)?)),
+                    _ => {
+                        let (old, new) = line.split_once('=').ok_or_else(|| AppError::InvalidSchema(line.to_owned()))?;
+                        result.renames.push((old.trim().to_owned(), new.trim().to_owned()));
+                    },
+                }
+            }
+        }
+
+        for it in &self.flag_type {
+            let (column, t) = it.rsplit_once('=').ok_or_else(|| AppError::InvalidSchema(it.to_owned()))?;
+            result.types.push((column.to_owned(), parse_type(t)?));
+        }
+
+        for it in &self.flag_rename {
+            let (old, new) = it.split_once('=').ok_or_else(|| AppError::InvalidSchema(it.to_owned()))?;
+            result.renames.push((old.to_owned(), new.to_owned()));
+        }
+
+        Ok(result)
+    }
+
+    fn dialect(&self) -> Dialect {
+        Dialect {
+            delimiter: self.flag_d.unwrap_or(','),
+            quote: self.flag_quote,
+            escape: self.flag_escape,
+            comment: self.flag_comment.clone(),
+            trim: self.flag_trim,
+            header_line: self.flag_header_line,
+            skip_footer: self.flag_skip_footer,
+            ragged: self.flag_ragged,
+        }
+    }
+
+    fn flatten(&self) -> Flatten {
+        Flatten { delimiter: self.flag_flatten_delimiter.clone(), max_depth: self.flag_flatten_depth }
+    }
+}
+
+
+fn parse_type(name: &str) -> AppResult<Type> {
+    Type::parse(name).ok_or_else(|| AppError::InvalidSchema(format!("Unknown type: {}", name)))
 }
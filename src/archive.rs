@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use flate2::read::GzDecoder;
+use glob::Pattern;
+
+use crate::errors::AppResult;
+
+
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+
+pub struct Member {
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+
+/// Decompress the members (of zip, tar or tar.gz) matching `pattern` into memory.
+/// Empty `pattern` matches every member.
+pub fn members(path: &str, pattern: &str) -> AppResult<Vec<Member>> {
+    let pattern = Pattern::new(if pattern.is_empty() { "*" } else { pattern })?;
+
+    let mut file = File::open(path)?;
+    let mut magic = vec![];
+    (&mut file).take(ZIP_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut result = vec![];
+
+    if magic.starts_with(ZIP_MAGIC) {
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+        for index in 0 .. archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if !entry.is_file() || !pattern.matches(entry.name()) {
+                continue;
+            }
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            result.push(Member { name: entry.name().to_owned(), content });
+        }
+    } else {
+        let reader: Box<dyn Read> = if magic.starts_with(GZIP_MAGIC) {
+            Box::new(GzDecoder::new(BufReader::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().into_owned();
+            if !pattern.matches(&name) {
+                continue;
+            }
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            result.push(Member { name, content });
+        }
+    }
+
+    Ok(result)
+}
@@ -1,39 +1,56 @@
 
+use std::collections::HashMap;
 use std::fs::{File, metadata, remove_file};
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::Path;
+use std::time::Instant;
 
-use encoding::DecoderTrap;
-use encoding::label::encoding_from_whatwg_label;
 use regex::Regex;
 use rusqlite::Transaction;
 
+use crate::archive;
+use crate::db::TxExt;
 use crate::errors::{AppError, AppResult, AppResultU};
-use crate::loader::{Config, Loader, self};
+use crate::files;
+use crate::loader::{BinaryLoader, Config, FTS_TABLE, Loader, MERGED_TABLE, self};
+use crate::report::Report;
+use crate::sql;
+use crate::text;
 use crate::types::*;
+use crate::ui;
 
 
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum State {
+    /// Some of the files have been changed, added or removed
+    Changed,
     Fresh,
     Nothing,
     Stale,
 }
 
+/// Input content, not yet read
+enum Content<'a> {
+    Bytes(&'a [u8]),
+    Input(&'a Input<'a>),
+}
+
 pub struct Cache<'a> {
     source: &'a Source,
     tx: Transaction<'a>,
 }
 
 pub enum Source {
+    /// Existing SQLite database, used as is
+    Database(String),
     File(String),
     Temp(mktemp::Temp),
 }
 
 
 impl<'a> Cache<'a> {
-    pub fn format(&self) -> AppResult<String> {
+    pub fn meta(&self, name: &str) -> AppResult<String> {
         let meta: u32 = self.tx.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'meta'", [], |row| row.get(0))?;
         match meta {
             0 => {
@@ -41,9 +58,9 @@ impl<'a> Cache<'a> {
                 Ok("".to_owned())
             },
             1 => {
-                let result = self.tx.query_row("SELECT value FROM meta WHERE name = 'format'", [], |row| row.get(0));
+                let result = self.tx.query_row("SELECT value FROM meta WHERE name = ?", [name], |row| row.get(0));
                 match result {
-                    Ok(format) => Ok(format),
+                    Ok(value) => Ok(value),
                     Err(rusqlite::Error::QueryReturnedNoRows) => Ok("".to_owned()),
                     Err(err) => Err(AppError::Sql(err)),
                 }
@@ -56,53 +73,171 @@ impl<'a> Cache<'a> {
         Self { source, tx }
     }
 
-    pub fn refresh(self, format: &Format, input: &Input, config: &Config, encoding: &Option<String>) -> AppResultU {
-        let source = read_file(input, encoding)?;
-
-        let load = |loader: &dyn Loader| {
-            loader.load(&self.tx, &source, config)
+    /// `incremental`: Reload only the changed files of `Input::Glob`
+    pub fn refresh(self, format: &Format, input: &Input, config: &Config, decoding: &Decoding, incremental: bool) -> AppResult<Report> {
+        let mut encodings = Vec::<&str>::new();
+        let mut detected = |it: Option<&'static str>| {
+            if let Some(it) = it.filter(|it| !encodings.contains(it)) {
+                encodings.push(it);
+            }
         };
 
-        match format {
-            Format::Csv(delimiter) =>
-                load(&loader::Csv { delimiter: *delimiter })?,
-            Format::Json =>
-                load(&loader::Json())?,
-            Format::Ltsv =>
-                load(&loader::Ltsv())?,
-            Format::Regex(ref format) =>
-                load(&loader::Regex { format: Regex::new(format)? })?,
-            Format::Simple =>
-                load(&loader::Simple { delimiter: Regex::new(r"[ \t]+")? })?,
+        let reject = self.prepare_rejected(format, incremental)?;
+
+        match *input {
+            Input::Glob(pattern) => {
+                let files = files::list(pattern)?;
+                if files.is_empty() {
+                    return Err(AppError::Fixed("No files matched"));
+                }
+
+                let mut current = HashMap::<String, String>::new();
+                for path in &files {
+                    current.insert(path.clone(), files::stamp(path)?);
+                }
+                let previous = if incremental {
+                    self.tx.replace_table("n", MERGED_TABLE)?;
+                    self.stamps()?
+                } else {
+                    self.tx.execute(&format!("DROP TABLE IF EXISTS {}", MERGED_TABLE), [])?;
+                    HashMap::new()
+                };
+
+                for path in previous.keys().filter(|it| current.get(*it) != previous.get(*it)) {
+                    let (source_column, _) = self.tx.provenance_columns(MERGED_TABLE)?;
+                    self.tx.execute(
+                        &format!("DELETE FROM {} WHERE {} = ?", sql::quote_identifier(MERGED_TABLE), sql::quote_identifier(&source_column)),
+                        [path])?;
+                    if reject {
+                        self.tx.execute(&format!("DELETE FROM {} WHERE source = ?", sql::quote_identifier(loader::REJECTED_TABLE)), [path])?;
+                    }
+                }
+                for path in files.iter().filter(|it| current.get(*it) != previous.get(*it)) {
+                    eprintln!("file: {}", path);
+                    detected(self.load(format, &Content::Input(&Input::File(path)), config, decoding)?);
+                    self.set_rejected_source(reject, path)?;
+                    self.tx.append_table("n", MERGED_TABLE, path)?;
+                }
+
+                self.tx.replace_table(MERGED_TABLE, "n")?;
+                self.set_stamps(&current)?;
+            },
+            Input::Archive(path, pattern) => {
+                let members = archive::members(path, pattern)?;
+                if members.is_empty() {
+                    return Err(AppError::Fixed("No archive members matched"));
+                }
+                for member in members {
+                    detected(self.load(format, &Content::Bytes(&member.content), config, decoding)?);
+                    self.set_rejected_source(reject, &member.name)?;
+                    if !config.member_tables {
+                        self.tx.append_table("n", MERGED_TABLE, &member.name)?;
+                    } else if loader::is_reserved_table(&member.name) {
+                        eprintln!("Skip member: {}", member.name);
+                    } else {
+                        self.tx.replace_table("n", &member.name)?;
+                    }
+                }
+                if !config.member_tables {
+                    self.tx.replace_table(MERGED_TABLE, "n")?;
+                }
+            },
+            _ => {
+                detected(self.load(format, &Content::Input(input), config, decoding)?);
+                if let Input::File(path) = *input {
+                    self.set_rejected_source(reject, path)?;
+                }
+            },
         }
 
+        let started = Instant::now();
+        self.index(config)?;
+        ui::record("index", started.elapsed());
+
         if let Source::File(_) = self.source {
-            let updated = self.tx.execute("UPDATE meta SET value = ? WHERE name = 'format';", &[&format.to_sql_literal()])?;
-            match updated {
-                0 => {
-                    self.tx.execute("INSERT INTO meta VALUES('format', ?);", &[&format.to_sql_literal()])?;
-                },
-                1 => (),
-                n => panic!("UPDATE has returned: {}", n),
-            }
+            self.tx.set_meta("format", &format.to_sql_literal())?;
+            self.tx.set_meta("input", &input.to_sql_literal())?;
+            self.tx.set_meta("config", &config.to_sql_literal())?;
+            self.tx.set_meta("encoding", &encodings.join(","))?;
+            self.tx.set_meta("filter", config.filter.as_deref().unwrap_or(""))?;
+            self.set_indexing(config)?;
         }
 
+        let tables = self.tx.tables()?;
+        let tables: Vec<&str> = tables.iter().map(AsRef::as_ref).filter(|it| !is_internal_table(it)).collect();
+        let cache = self.source.as_ref().to_str().map(str::to_owned);
+        let report = Report::new(&self.tx, &tables, cache, format.name(), encodings.join(","))?;
+        self.tx.set_meta("report", &report.to_json()?)?;
+
         self.tx.commit()?;
+        Ok(report)
+    }
+
+    /// Create `n_rejected` for `--ragged reject`, keeping the rows of the unchanged files on incremental refreshes, or drop it.
+    /// Returns whether the rows are rejected.
+    fn prepare_rejected(&self, format: &Format, incremental: bool) -> AppResult<bool> {
+        let reject = matches!(*format, Format::Csv(ref dialect) if dialect.ragged == Ragged::Reject);
+        let table = sql::quote_identifier(loader::REJECTED_TABLE);
+        if !reject || !incremental {
+            self.tx.execute(&format!("DROP TABLE IF EXISTS {}", table), [])?;
+        }
+        if reject {
+            self.tx.execute(&format!("CREATE TABLE IF NOT EXISTS {} (source text, line integer, fields text)", table), [])?;
+        }
+        Ok(reject)
+    }
+
+    /// Record `source` (file path or archive member name) in the rows rejected by the last load
+    fn set_rejected_source(&self, reject: bool, source: &str) -> AppResultU {
+        if reject {
+            self.tx.execute(&format!("UPDATE {} SET source = ? WHERE source IS NULL", sql::quote_identifier(loader::REJECTED_TABLE)), [source])?;
+        }
         Ok(())
     }
 
-    pub fn state(&self, input: &Input, format: &Format) -> AppResult<State> {
+    pub fn state(&self, input: &Input, format: &Format, config: &Config) -> AppResult<State> {
         match *input {
             Input::Stdin => Ok(State::Nothing),
-            Input::File(input_filepath) => {
+            Input::Glob(pattern) => {
                 match self.source {
                     Source::File(ref cache_filepath) => {
                         if !Path::new(cache_filepath).exists() {
                             return Ok(State::Nothing)
                         }
-                        let input = metadata(input_filepath)?.modified()?;
+                        let same =
+                            format.to_sql_literal() == self.meta("format")? &&
+                            input.to_sql_literal() == self.meta("input")? &&
+                            config.to_sql_literal() == self.meta("config")?;
+                        if !same {
+                            return Ok(State::Stale);
+                        }
+                        let mut current = HashMap::<String, String>::new();
+                        for path in files::list(pattern)? {
+                            let stamp = files::stamp(&path)?;
+                            current.insert(path, stamp);
+                        }
+                        Ok(if current == self.stamps()? {
+                            State::Fresh
+                        } else {
+                            State::Changed
+                        })
+                    },
+                    _ => Ok(State::Nothing),
+                }
+            },
+            Input::File(input_filepath) | Input::Archive(input_filepath, _) => {
+                match self.source {
+                    Source::File(ref cache_filepath) => {
+                        if !Path::new(cache_filepath).exists() {
+                            return Ok(State::Nothing)
+                        }
+                        let modified = metadata(input_filepath)?.modified()?;
                         let cache = metadata(cache_filepath)?.modified()?;
-                        Ok(if input < cache && format.to_sql_literal() == self.format()? {
+                        let same =
+                            format.to_sql_literal() == self.meta("format")? &&
+                            input.to_sql_literal() == self.meta("input")? &&
+                            config.to_sql_literal() == self.meta("config")?;
+                        Ok(if modified < cache && same {
                             State::Fresh
                         } else {
                             State::Stale
@@ -113,6 +248,156 @@ impl<'a> Cache<'a> {
             }
         }
     }
+
+    /// Returns the encoding of the text content
+    fn load(&self, format: &Format, content: &Content, config: &Config, decoding: &Decoding) -> AppResult<Option<&'static str>> {
+        let load = |loader: &dyn Loader| {
+            let started = Instant::now();
+            let (text, encoding) = content.text(decoding)?;
+            ui::record("decode", started.elapsed());
+            loader.load(&self.tx, &text, config)?;
+            loader::finish_tables(&self.tx, config)?;
+            Ok(Some(encoding))
+        };
+        let load_binary = |loader: &dyn BinaryLoader| {
+            content.with_file(|file| loader.load(&self.tx, file, config))?;
+            loader::finish_tables(&self.tx, config)?;
+            Ok(None)
+        };
+
+        match format {
+            Format::Columnar =>
+                load_binary(&loader::Columnar()),
+            Format::Csv(ref dialect) =>
+                load(&loader::Csv { dialect: dialect.clone() }),
+            Format::FixedWidth(ref ranges) =>
+                load(&loader::FixedWidth::new(ranges)?),
+            Format::Html(index) =>
+                load(&loader::Html { index: *index }),
+            Format::Json(ref flatten) =>
+                load(&loader::Json { flatten: flatten.clone() }),
+            Format::Logfmt =>
+                load(&loader::Logfmt()),
+            Format::Ltsv =>
+                load(&loader::Ltsv()),
+            Format::Markdown(index) =>
+                load(&loader::Markdown { index: *index }),
+            Format::Regex(ref format) =>
+                load(&loader::Regex { format: Regex::new(format)? }),
+            Format::Simple =>
+                load(&loader::Simple { delimiter: Regex::new(r"[ \t]+")? }),
+            Format::Spreadsheet(ref sheet) =>
+                load_binary(&loader::Spreadsheet { sheet: sheet.clone() }),
+            Format::Toml(ref flatten, ref table) =>
+                load(&loader::Toml { flatten: flatten.clone(), table: table.clone() }),
+            Format::Xml(ref flatten, ref record) =>
+                load(&loader::Xml { flatten: flatten.clone(), record: record.clone() }),
+            Format::Yaml(ref flatten) =>
+                load(&loader::Yaml { flatten: flatten.clone() }),
+        }
+    }
+
+    /// Apply the changed primary key, indexes and FTS columns to the fresh cache.
+    /// Returns the stored report.
+    pub fn reindex(self, config: &Config) -> AppResult<String> {
+        let started = Instant::now();
+        self.index(config)?;
+        ui::record("index", started.elapsed());
+        self.set_indexing(config)?;
+
+        let report = self.meta("report")?;
+        self.tx.commit()?;
+        Ok(report)
+    }
+
+    /// Take the primary key, indexes and FTS columns not given in `config` from the last refresh
+    pub fn reuse_indexing(&self, config: &mut Config) -> AppResultU {
+        if config.primary_key.is_none() {
+            if let Some(it) = self.indexing("primary_key")? {
+                config.primary_key = serde_json::from_str(&it)?;
+            }
+        }
+        if config.indexes.is_empty() {
+            if let Some(it) = self.indexing("indexes")? {
+                config.indexes = serde_json::from_str(&it)?;
+            }
+        }
+        if config.fts.is_none() {
+            if let Some(it) = self.indexing("fts")? {
+                config.fts = serde_json::from_str(&it)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the primary key, indexes or FTS columns differ from the last refresh
+    pub fn is_indexing_changed(&self, config: &Config) -> AppResult<bool> {
+        Ok(
+            self.indexing("primary_key")? != Some(serde_json::to_string(&config.primary_key)?) ||
+            self.indexing("indexes")? != Some(serde_json::to_string(&config.indexes)?) ||
+            self.indexing("fts")? != Some(serde_json::to_string(&config.fts)?))
+    }
+
+    /// JSON in `meta`, which does not take part in the freshness of the cache
+    fn indexing(&self, name: &str) -> AppResult<Option<String>> {
+        Ok(Some(self.meta(name)?).filter(|it| !it.is_empty()))
+    }
+
+    fn set_indexing(&self, config: &Config) -> AppResultU {
+        self.tx.set_meta("primary_key", &serde_json::to_string(&config.primary_key)?)?;
+        self.tx.set_meta("indexes", &serde_json::to_string(&config.indexes)?)?;
+        self.tx.set_meta("fts", &serde_json::to_string(&config.fts)?)?;
+        Ok(())
+    }
+
+    /// Primary key, indexes and full-text search table of `n`
+    fn index(&self, config: &Config) -> AppResultU {
+        self.tx.execute(&format!("DROP TABLE IF EXISTS {}", FTS_TABLE), [])?;
+        self.tx.drop_indexes("n")?;
+        if config.primary_key.is_none() && config.indexes.is_empty() && config.fts.is_none() {
+            return Ok(());
+        }
+        if self.tx.columns("n")?.is_empty() {
+            eprintln!("Skip indexes: no table n");
+            return Ok(());
+        }
+
+        if let Some(ref columns) = config.primary_key {
+            self.tx.set_primary_key("n", columns)?;
+        }
+        for columns in &config.indexes {
+            self.tx.create_index("n", columns)?;
+        }
+        if let Some(ref columns) = config.fts {
+            self.tx.create_fts("n", FTS_TABLE, columns)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_stamps(&self, stamps: &HashMap<String, String>) -> AppResultU {
+        self.tx.execute("CREATE TABLE IF NOT EXISTS files (path TEXT PRIMARY KEY, stamp TEXT);", [])?;
+        self.tx.execute("DELETE FROM files;", [])?;
+        for (path, stamp) in stamps {
+            self.tx.execute("INSERT INTO files VALUES(?, ?);", [path, stamp])?;
+        }
+        Ok(())
+    }
+
+    fn stamps(&self) -> AppResult<HashMap<String, String>> {
+        let mut result = HashMap::new();
+        let exists: u32 = self.tx.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'files'", [], |row| row.get(0))?;
+        if exists == 0 {
+            return Ok(result);
+        }
+        let mut stmt = self.tx.prepare("SELECT path, stamp FROM files")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (path, stamp) = row?;
+            result.insert(path, stamp);
+        }
+        Ok(result)
+    }
+
 }
 
 
@@ -123,13 +408,40 @@ impl State {
 }
 
 
+impl<'a> Content<'a> {
+    fn text(&self, decoding: &Decoding) -> AppResult<(String, &'static str)> {
+        match *self {
+            Content::Bytes(bytes) => text::decode(bytes, decoding),
+            Content::Input(input) => read_file(input, decoding),
+        }
+    }
+
+    /// Binary loaders read a seekable file, so other content is written to a temporary file
+    fn with_file<F: FnOnce(&File) -> AppResultU>(&self, f: F) -> AppResultU {
+        let temp = mktemp::Temp::new_file()?;
+        match *self {
+            Content::Input(Input::File(input_filepath)) =>
+                return f(&File::open(input_filepath)?),
+            Content::Input(Input::Archive(..)) | Content::Input(Input::Glob(_)) => panic!("BUG"),
+            Content::Input(Input::Stdin) => {
+                io::copy(&mut io::stdin(), &mut File::create(&temp)?)?;
+            },
+            Content::Bytes(bytes) => {
+                File::create(&temp)?.write_all(bytes)?;
+            },
+        }
+        f(&File::open(&temp)?)
+    }
+}
+
+
 impl Source {
     pub fn remove_file(&self) -> AppResultU {
         use Source::*;
 
         match *self {
             File(ref path) => remove_file(path)?,
-            Temp(_) => (),
+            Database(_) | Temp(_) => (),
         }
 
         Ok(())
@@ -139,43 +451,32 @@ impl Source {
 impl AsRef<Path> for Source {
     fn as_ref(&self) -> &Path {
         match *self {
-            Source::File(ref path) => Path::new(path),
+            Source::Database(ref path) | Source::File(ref path) => Path::new(path),
             Source::Temp(ref path) => path.as_ref(),
         }
     }
 }
 
 
-fn read_file(input: &Input, encoding: &Option<String>) -> AppResult<String> {
-    let mut buffer = String::new();
+fn read_file(input: &Input, decoding: &Decoding) -> AppResult<(String, &'static str)> {
+    let mut bin: Vec<u8> = vec![];
 
-    if let Some(ref encoding) = *encoding {
-        let encoding = encoding_from_whatwg_label(encoding).ok_or("Invalid encoding name")?;
-        let mut bin: Vec<u8> = vec![];
-        match *input {
-            Input::File(ref input_filepath) => {
-                let mut file = File::open(input_filepath)?;
-                file.read_to_end(&mut bin)?;
-            },
-            Input::Stdin => {
-                io::stdin().read_to_end(&mut bin)?;
-            }
-        }
-        buffer = match encoding.decode(&bin, DecoderTrap::Replace) {
-            Ok(s) => s,
-            Err(s) => s.to_string(),
-        };
-    } else {
-        match *input {
-            Input::File(input_filepath) => {
-                let mut file = File::open(input_filepath)?;
-                file.read_to_string(&mut buffer)?;
-            },
-            Input::Stdin => {
-                io::stdin().read_to_string(&mut buffer)?;
-            }
-        }
+    match *input {
+        Input::File(input_filepath) => {
+            let mut file = File::open(input_filepath)?;
+            file.read_to_end(&mut bin)?;
+        },
+        Input::Stdin => {
+            io::stdin().read_to_end(&mut bin)?;
+        },
+        Input::Archive(..) | Input::Glob(_) => panic!("BUG"),
     }
 
-    Ok(buffer)
+    text::decode(&bin, decoding)
+}
+
+
+/// Tables kept by nq for its own use
+fn is_internal_table(name: &str) -> bool {
+    ["meta", "files", MERGED_TABLE].contains(&name) || name == FTS_TABLE || name.starts_with(&format!("{}_", FTS_TABLE))
 }
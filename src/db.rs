@@ -1,18 +1,35 @@
 
 use rusqlite::Transaction;
 
-use crate::errors::AppResultU;
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::sql;
 use crate::types::Type;
 
 
 
+pub const LINE_COLUMN: &str = "line";
+pub const SOURCE_COLUMN: &str = "source";
+
+
 pub trait TxExt {
-    fn create_table(&self, types: &[Type], header: &[&str]) -> AppResultU;
+    fn create_named_table(&self, table: &str, types: &[Type], header: &[&str]) -> AppResultU;
+    fn append_table(&self, from: &str, into: &str, source: &str) -> AppResultU;
+    fn columns(&self, table: &str) -> AppResult<Vec<(String, String)>>;
+    fn provenance_columns(&self, table: &str) -> AppResult<(String, String)>;
+    fn create_fts(&self, table: &str, fts: &str, columns: &[String]) -> AppResultU;
+    fn create_index(&self, table: &str, columns: &[String]) -> AppResultU;
+    fn drop_indexes(&self, table: &str) -> AppResultU;
+    fn project_table(&self, table: &str, columns: &[String]) -> AppResultU;
+    fn replace_table(&self, from: &str, to: &str) -> AppResultU;
+    fn set_primary_key(&self, table: &str, columns: &[String]) -> AppResultU;
+    fn set_meta(&self, name: &str, value: &str) -> AppResultU;
+    fn tables(&self) -> AppResult<Vec<String>>;
 }
 
 impl<'a> TxExt for Transaction<'a> {
-    fn create_table(&self, types: &[Type], header: &[&str]) -> AppResultU {
-        let mut create = "CREATE TABLE n (".to_owned();
+    fn create_named_table(&self, table: &str, types: &[Type], header: &[&str]) -> AppResultU {
+        let table = sql::quote_string(table);
+        let mut create = format!("CREATE TABLE {} (", table);
         let mut first = true;
         for (i, name) in header.iter().enumerate() {
             let name = name.replace("'", "''");
@@ -30,9 +47,226 @@ impl<'a> TxExt for Transaction<'a> {
         }
         create.push(')');
 
-        self.execute("DROP TABLE IF EXISTS n", []).unwrap();
+        self.execute(&format!("DROP TABLE IF EXISTS {}", table), [])?;
         self.execute(&create, [])?;
 
         Ok(())
     }
+
+    /// Move the rows of `from` into `into` with the `source` and `line` (`rowid` of `from`) columns,
+    /// adding the missing columns to `into`.
+    /// The provenance columns are the first two of `into`, renamed `source_2`, `line_2` .. away from the columns of `from`.
+    fn append_table(&self, from: &str, into: &str, source: &str) -> AppResultU {
+        let columns = self.columns(from)?;
+        let existing = self.columns(into)?;
+        let mut taken: Vec<String> = columns.iter().chain(existing.iter()).map(|(name, _)| name.to_owned()).collect();
+
+        if existing.is_empty() {
+            let source_column = unique_name(SOURCE_COLUMN, &taken);
+            taken.push(source_column.clone());
+            let line_column = unique_name(LINE_COLUMN, &taken);
+            self.execute(
+                &format!(
+                    "CREATE TABLE {} ({} text, {} integer)",
+                    sql::quote_identifier(into), sql::quote_identifier(&source_column), sql::quote_identifier(&line_column)),
+                [])?;
+        } else {
+            for ((name, _), base) in existing.iter().zip(&[SOURCE_COLUMN, LINE_COLUMN]) {
+                if columns.iter().any(|(it, _)| it.eq_ignore_ascii_case(name)) {
+                    let renamed = unique_name(base, &taken);
+                    eprintln!("Rename column: {} -> {}", name, renamed);
+                    self.execute(
+                        &format!(
+                            "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                            sql::quote_identifier(into), sql::quote_identifier(name), sql::quote_identifier(&renamed)),
+                        [])?;
+                    taken.push(renamed);
+                }
+            }
+        }
+
+        let (source_column, line_column) = self.provenance_columns(into)?;
+        let (source_column, line_column) = (sql::quote_identifier(&source_column), sql::quote_identifier(&line_column));
+        let existing = self.columns(into)?;
+        for (name, t) in &columns {
+            if !existing.iter().any(|(it, _)| it == name) {
+                self.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", sql::quote_identifier(into), sql::quote_identifier(name), t), [])?;
+            }
+        }
+
+        let names: Vec<String> = columns.iter().map(|(name, _)| sql::quote_identifier(name)).collect();
+        let names = names.join(",");
+        self.execute(
+            &format!(
+                "INSERT INTO {} ({},{},{}) SELECT ?,rowid,{} FROM {}",
+                sql::quote_identifier(into), source_column, line_column, names, names, sql::quote_identifier(from)),
+            [source])?;
+        self.execute(&format!("DROP TABLE {}", sql::quote_identifier(from)), [])?;
+
+        Ok(())
+    }
+
+    /// The `source` and `line` columns of a table built by `append_table`
+    fn provenance_columns(&self, table: &str) -> AppResult<(String, String)> {
+        let mut columns = self.columns(table)?.into_iter().map(|(name, _)| name);
+        match (columns.next(), columns.next()) {
+            (Some(source), Some(line)) => Ok((source, line)),
+            _ => Err(AppError::UnknownColumn(SOURCE_COLUMN.to_owned())),
+        }
+    }
+
+    /// Column names and declared types
+    fn columns(&self, table: &str) -> AppResult<Vec<(String, String)>> {
+        let mut stmt = self.prepare(&format!("PRAGMA table_info({})", sql::quote_identifier(table)))?;
+        let columns = stmt.query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?;
+        let mut result = vec![];
+        for column in columns {
+            result.push(column?);
+        }
+        Ok(result)
+    }
+
+    /// FTS5 table `fts` indexing `columns` of `table` by `rowid`
+    fn create_fts(&self, table: &str, fts: &str, columns: &[String]) -> AppResultU {
+        let existing = self.columns(table)?;
+        for column in columns {
+            if !existing.iter().any(|(name, _)| name == column) {
+                return Err(AppError::UnknownColumn(column.to_owned()));
+            }
+        }
+
+        let names: Vec<String> = columns.iter().map(|it| sql::quote_identifier(it)).collect();
+        self.execute(
+            &format!(
+                "CREATE VIRTUAL TABLE {} USING fts5({}, content={}, content_rowid='rowid')",
+                sql::quote_identifier(fts), names.join(","), sql::quote_string(table)),
+            [])?;
+        self.execute(&format!("INSERT INTO {} ({}) VALUES('rebuild')", sql::quote_identifier(fts), sql::quote_identifier(fts)), [])?;
+        Ok(())
+    }
+
+    /// Index named `TABLE_COLUMN_COLUMN..`
+    fn create_index(&self, table: &str, columns: &[String]) -> AppResultU {
+        let existing = self.columns(table)?;
+        for column in columns {
+            if !existing.iter().any(|(name, _)| name == column) {
+                return Err(AppError::UnknownColumn(column.to_owned()));
+            }
+        }
+
+        let name = format!("{}_{}", table, columns.join("_"));
+        let names: Vec<String> = columns.iter().map(|it| sql::quote_identifier(it)).collect();
+        self.execute(
+            &format!("CREATE INDEX {} ON {} ({})", sql::quote_identifier(&name), sql::quote_identifier(table), names.join(",")),
+            [])?;
+        Ok(())
+    }
+
+    /// Indexes created by `create_index`, not the ones of the primary key
+    fn drop_indexes(&self, table: &str) -> AppResultU {
+        let mut stmt = self.prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ? AND sql IS NOT NULL")?;
+        let names = stmt.query_map([table], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        for name in names {
+            self.execute(&format!("DROP INDEX {}", sql::quote_identifier(&name)), [])?;
+        }
+        Ok(())
+    }
+
+    /// Keep only `columns` of `table` in the order, with `rowid`
+    fn project_table(&self, table: &str, columns: &[String]) -> AppResultU {
+        let existing = self.columns(table)?;
+        let mut types = vec![];
+        for column in columns {
+            let t = existing.iter().find(|(name, _)| name == column).ok_or_else(|| AppError::UnknownColumn(column.to_owned()))?;
+            types.push(match t.1.to_lowercase().as_str() {
+                "integer" => Type::Int,
+                "real" => Type::Real,
+                _ => Type::Text,
+            });
+        }
+
+        let projected = format!("{}_projected", table);
+        let header: Vec<&str> = columns.iter().map(AsRef::as_ref).collect();
+        self.create_named_table(&projected, &types, header.as_slice())?;
+        let names: Vec<String> = columns.iter().map(|it| sql::quote_identifier(it)).collect();
+        let names = names.join(",");
+        self.execute(
+            &format!(
+                "INSERT INTO {} (rowid,{}) SELECT rowid,{} FROM {}",
+                sql::quote_identifier(&projected), names, names, sql::quote_identifier(table)),
+            [])?;
+        self.replace_table(&projected, table)
+    }
+
+    fn replace_table(&self, from: &str, to: &str) -> AppResultU {
+        let (from, to) = (sql::quote_identifier(from), sql::quote_identifier(to));
+        self.execute(&format!("DROP TABLE IF EXISTS {}", to), [])?;
+        self.execute(&format!("ALTER TABLE {} RENAME TO {}", from, to), [])?;
+        Ok(())
+    }
+
+    /// Rebuild `table` with the primary key.
+    /// A single `integer` key with only integer values becomes the `rowid`, otherwise `rowid` is kept.
+    fn set_primary_key(&self, table: &str, columns: &[String]) -> AppResultU {
+        let existing = self.columns(table)?;
+        for column in columns {
+            if !existing.iter().any(|(name, _)| name == column) {
+                return Err(AppError::UnknownColumn(column.to_owned()));
+            }
+        }
+
+        let integer_key = columns.len() == 1 && existing.iter().any(|(name, t)| *name == columns[0] && t.eq_ignore_ascii_case("integer"));
+        let is_rowid = integer_key && {
+            let q = format!("SELECT COUNT(*) FROM {} WHERE typeof({}) <> 'integer'", sql::quote_identifier(table), sql::quote_identifier(&columns[0]));
+            0 == self.query_row(&q, [], |row| row.get::<_, i64>(0))?
+        };
+
+        let keyed = format!("{}_keyed", table);
+        let definitions: Vec<String> = existing.iter().map(|(name, t)| {
+            // A single `integer` key is always an alias of `rowid`
+            let t = if integer_key && !is_rowid && *name == columns[0] { "text" } else { t };
+            format!("{} {}", sql::quote_identifier(name), t)
+        }).collect();
+        let key: Vec<String> = columns.iter().map(|it| sql::quote_identifier(it)).collect();
+        self.execute(
+            &format!("CREATE TABLE {} ({}, PRIMARY KEY ({}))", sql::quote_identifier(&keyed), definitions.join(","), key.join(",")),
+            [])?;
+
+        let names: Vec<String> = existing.iter().map(|(name, _)| sql::quote_identifier(name)).collect();
+        let names = if is_rowid { names.join(",") } else { format!("rowid,{}", names.join(",")) };
+        self.execute(
+            &format!("INSERT INTO {} ({}) SELECT {} FROM {}", sql::quote_identifier(&keyed), names, names, sql::quote_identifier(table)),
+            [])?;
+
+        self.replace_table(&keyed, table)
+    }
+
+    fn set_meta(&self, name: &str, value: &str) -> AppResultU {
+        self.execute("CREATE TABLE IF NOT EXISTS meta (name TEXT PRIMARY KEY, value TEXT);", [])?;
+        self.execute("INSERT OR REPLACE INTO meta VALUES(?, ?);", [name, value])?;
+        Ok(())
+    }
+
+    /// Tables except the internal ones of SQLite
+    fn tables(&self) -> AppResult<Vec<String>> {
+        let mut stmt = self.prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get(0))?;
+        let mut result = vec![];
+        for name in names {
+            result.push(name?);
+        }
+        Ok(result)
+    }
+}
+
+
+/// `base`, or `base_2`, `base_3` .. not in `taken` (case insensitive as SQLite)
+fn unique_name(base: &str, taken: &[String]) -> String {
+    let mut name = base.to_owned();
+    let mut n = 1;
+    while taken.iter().any(|it| it.eq_ignore_ascii_case(&name)) {
+        n += 1;
+        name = format!("{}_{}", base, n);
+    }
+    name
 }
@@ -12,18 +12,44 @@ pub type AppResultU = Result<(), AppError>;
 pub enum AppError {
     #[fail(display = "IO Error: {}", 0)]
     Io(std::io::Error),
-    #[fail(display = "CSV Error: {}", 0)]
-    Csv(quick_csv::error::Error),
+    #[fail(display = "Arrow Error: {}", 0)]
+    Arrow(arrow::error::ArrowError),
+    #[fail(display = "CSV Error: {} (line {})", 0, 1)]
+    Csv(String, usize),
+    #[fail(display = "Decoding Error: Invalid {} at byte {} (line {})", encoding, offset, line)]
+    Decode { encoding: &'static str, offset: usize, line: usize },
     #[fail(display = "Few columns")]
     FewColumns,
     #[fail(display = "Error: {}", 0)]
     Fixed(&'static str),
+    #[fail(display = "Glob Error: {}", 0)]
+    Glob(glob::PatternError),
     #[fail(display = "Json Error: {}", 0)]
     Json(serde_json::Error),
+    #[fail(display = "Column name collision: {}", 0)]
+    NameCollision(String),
+    #[fail(display = "Insert Error: {} (line {})", 0, 1)]
+    Insert(String, usize),
+    #[fail(display = "Invalid schema: {}", 0)]
+    InvalidSchema(String),
+    #[fail(display = "Parquet Error: {}", 0)]
+    Parquet(parquet::errors::ParquetError),
     #[fail(display = "Regex Error: {}", 0)]
     Regex(regex::Error),
+    #[fail(display = "Spreadsheet Error: {}", 0)]
+    Spreadsheet(calamine::Error),
     #[fail(display = "SQL Error: {}", 0)]
     Sql(rusqlite::Error),
+    #[fail(display = "TOML Error: {}", 0)]
+    Toml(toml::de::Error),
+    #[fail(display = "XML Error: {}", 0)]
+    Xml(roxmltree::Error),
+    #[fail(display = "YAML Error: {}", 0)]
+    Yaml(serde_yaml::Error),
+    #[fail(display = "Unknown column: {}", 0)]
+    UnknownColumn(String),
+    #[fail(display = "Zip Error: {}", 0)]
+    Zip(zip::result::ZipError),
 }
 
 
@@ -37,11 +63,18 @@ macro_rules! define_error {
     }
 }
 
-define_error!(quick_csv::error::Error, Csv);
+define_error!(arrow::error::ArrowError, Arrow);
+define_error!(glob::PatternError, Glob);
+define_error!(parquet::errors::ParquetError, Parquet);
 define_error!(regex::Error, Regex);
+define_error!(calamine::Error, Spreadsheet);
 define_error!(rusqlite::Error, Sql);
 define_error!(serde_json::Error, Json);
 define_error!(std::io::Error, Io);
+define_error!(toml::de::Error, Toml);
+define_error!(roxmltree::Error, Xml);
+define_error!(serde_yaml::Error, Yaml);
+define_error!(zip::result::ZipError, Zip);
 
 
 impl From<&'static str> for AppError {
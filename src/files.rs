@@ -0,0 +1,50 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::errors::AppResult;
+
+
+
+const CACHE_MARK: &str = ".nq-cache.sqlite";
+
+
+/// Regular files in the directory, or matching the glob pattern, except caches
+pub fn list(pattern: &str) -> AppResult<Vec<String>> {
+    let mut result = vec![];
+
+    if Path::new(pattern).is_dir() {
+        for entry in fs::read_dir(pattern)? {
+            let path = entry?.path();
+            if path.is_file() {
+                result.push(path.to_string_lossy().into_owned());
+            }
+        }
+    } else {
+        for path in glob::glob(pattern)? {
+            let path = path.map_err(io::Error::from)?;
+            if path.is_file() {
+                result.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    result.retain(|it| !it.contains(CACHE_MARK));
+    result.sort();
+    Ok(result)
+}
+
+/// Modification time and size, to detect the changed files
+pub fn stamp(path: &str) -> AppResult<String> {
+    let meta = fs::metadata(path)?;
+    let modified = meta.modified()?.duration_since(UNIX_EPOCH).map(|it| it.as_nanos()).unwrap_or(0);
+    Ok(format!("{}:{}", modified, meta.len()))
+}
+
+/// Cache path for the directory or the glob pattern
+pub fn cache_path(pattern: &str) -> String {
+    let mut path: String = pattern.trim_end_matches('/').chars().map(|it| if "*?[]{}".contains(it) { '_' } else { it }).collect();
+    path.push_str(CACHE_MARK);
+    path
+}
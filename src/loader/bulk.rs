@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::Transaction;
+use rusqlite::types::Value;
+
+use crate::db::TxExt;
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::sql;
+
+
+
+/// Lower bound of `SQLITE_MAX_VARIABLE_NUMBER` among SQLite versions
+const MAX_VARIABLES: usize = 999;
+
+
+/// Buffers rows per column set, and inserts them by multi-row `INSERT`s with cached statements.
+/// The values of the columns missing in the table (dropped by the projection) are skipped.
+pub struct Inserter<'a, 'b> {
+    tx: &'a Transaction<'b>,
+    table: String,
+    /// Columns of the table, read at the first insert
+    existing: Option<HashSet<String>>,
+    /// By the joined column names
+    buffers: HashMap<String, Buffer>,
+    /// Number of the rows given so far, reported on errors for the rows without `rowid`
+    rows: usize,
+}
+
+struct Buffer {
+    /// Inserted column names
+    columns: Vec<String>,
+    /// Whether the value at each position is inserted, `None` for all
+    selected: Option<Vec<bool>>,
+    /// Flattened values
+    values: Vec<Value>,
+}
+
+
+impl<'a, 'b> Inserter<'a, 'b> {
+    pub fn new(tx: &'a Transaction<'b>, table: &str) -> Self {
+        Inserter { tx, table: table.to_owned(), existing: None, buffers: HashMap::new(), rows: 0 }
+    }
+
+    /// `columns` may start with `rowid`, which is reported as the line number on errors
+    pub fn insert(&mut self, columns: &[&str], values: Vec<Value>) -> AppResultU {
+        self.rows += 1;
+        if values.len() != columns.len() {
+            let line = match (columns.first(), values.first()) {
+                (Some(&"rowid"), Some(Value::Integer(line))) => *line as usize,
+                _ => self.rows,
+            };
+            return Err(AppError::Insert(format!("{} values for {} columns", values.len(), columns.len()), line));
+        }
+
+        let key = columns.join("\0");
+        if !self.buffers.contains_key(&key) {
+            let buffer = self.buffer(columns)?;
+            self.buffers.insert(key.clone(), buffer);
+        }
+
+        let buffer = self.buffers.get_mut(&key).expect("BUG");
+        match buffer.selected {
+            Some(ref selected) => buffer.values.extend(values.into_iter().zip(selected).filter(|(_, it)| **it).map(|(value, _)| value)),
+            None => buffer.values.extend(values),
+        }
+
+        if buffer.values.len() / buffer.columns.len().max(1) >= batch_rows(buffer.columns.len()) {
+            let buffer = self.buffers.remove(&key).expect("BUG");
+            self.flush(&buffer.columns, buffer.values)?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert the buffered rows
+    pub fn finish(mut self) -> AppResultU {
+        let buffers: Vec<_> = self.buffers.drain().map(|(_, it)| it).collect();
+        for buffer in buffers {
+            self.flush(&buffer.columns, buffer.values)?;
+        }
+        Ok(())
+    }
+
+    fn buffer(&mut self, columns: &[&str]) -> AppResult<Buffer> {
+        if self.existing.is_none() {
+            self.existing = Some(self.tx.columns(&self.table)?.into_iter().map(|(name, _)| name).collect());
+        }
+        let existing = self.existing.as_ref().expect("BUG");
+
+        let selected: Vec<bool> = columns.iter().map(|it| *it == "rowid" || existing.contains(*it)).collect();
+        let kept = columns.iter().zip(&selected).filter(|(_, it)| **it).map(|(name, _)| (*name).to_owned()).collect();
+        Ok(Buffer {
+            columns: kept,
+            selected: Some(selected).filter(|it| it.contains(&false)),
+            values: vec![],
+        })
+    }
+
+    fn flush(&self, columns: &[String], values: Vec<Value>) -> AppResultU {
+        if columns.is_empty() || values.is_empty() {
+            return Ok(());
+        }
+
+        let rows = values.len() / columns.len();
+        let mut stmt = self.tx.prepare_cached(&self.statement(columns, rows))?;
+        if stmt.execute(rusqlite::params_from_iter(values.iter())).is_ok() {
+            return Ok(());
+        }
+
+        // Find the failed row
+        let mut stmt = self.tx.prepare_cached(&self.statement(columns, 1))?;
+        for row in values.chunks(columns.len()) {
+            if let Err(err) = stmt.execute(rusqlite::params_from_iter(row.iter())) {
+                return Err(match (columns[0].as_str(), &row[0]) {
+                    ("rowid", Value::Integer(line)) => AppError::Insert(err.to_string(), *line as usize),
+                    _ => AppError::Sql(err),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&self, columns: &[String], rows: usize) -> String {
+        let names: Vec<String> = columns.iter().map(|it| sql::quote_identifier(it)).collect();
+        let row = format!("({})", super::qs(columns.len()));
+        let rows: Vec<&str> = (0 .. rows).map(|_| row.as_str()).collect();
+        format!("INSERT INTO {} ({}) VALUES {}", sql::quote_identifier(&self.table), names.join(","), rows.join(","))
+    }
+}
+
+
+/// Rows are inserted with their line number as `rowid`
+pub fn with_rowid<'c>(header: &[&'c str]) -> Vec<&'c str> {
+    Some("rowid").into_iter().chain(header.iter().cloned()).collect()
+}
+
+pub fn line(line: usize) -> Value {
+    Value::Integer(line as i64)
+}
+
+pub fn text<T: Into<String>>(value: Option<T>) -> Value {
+    value.map_or(Value::Null, |it| Value::Text(it.into()))
+}
+
+fn batch_rows(columns: usize) -> usize {
+    (MAX_VARIABLES / columns.max(1)).max(1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+
+    type Row = (i64, Option<String>, Option<String>, Option<String>);
+
+    fn rows(tx: &Transaction) -> Vec<Row> {
+        let mut stmt = tx.prepare("SELECT rowid, a, b, c FROM n ORDER BY rowid").unwrap();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    fn texts(values: &[&str]) -> Vec<Value> {
+        values.iter().map(|it| text(Some(*it))).collect()
+    }
+
+    #[test]
+    fn test_mixed_widths() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute("CREATE TABLE n (a text, b text, c text)", []).unwrap();
+
+        let mut inserter = Inserter::new(&tx, "n");
+        inserter.insert(&["rowid", "a", "b", "c"], Some(line(1)).into_iter().chain(texts(&["1", "2", "3"])).collect()).unwrap();
+        inserter.insert(&["rowid", "a"], Some(line(2)).into_iter().chain(texts(&["4"])).collect()).unwrap();
+        inserter.insert(&["rowid", "c", "a"], Some(line(3)).into_iter().chain(texts(&["5", "6"])).collect()).unwrap();
+        inserter.insert(&["rowid", "a", "b", "c"], Some(line(4)).into_iter().chain(texts(&["7", "8", "9"])).collect()).unwrap();
+        inserter.finish().unwrap();
+
+        let s = |it: &str| Some(it.to_owned());
+        assert_eq!(rows(&tx), vec![
+            (1, s("1"), s("2"), s("3")),
+            (2, s("4"), None, None),
+            (3, s("6"), None, s("5")),
+            (4, s("7"), s("8"), s("9")),
+        ]);
+    }
+
+    #[test]
+    fn test_values_not_matching_columns() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute("CREATE TABLE n (a text, b text, c text)", []).unwrap();
+
+        let mut inserter = Inserter::new(&tx, "n");
+        inserter.insert(&["rowid", "a", "b"], vec![line(1), text(Some("1")), text(Some("2"))]).unwrap();
+        assert!(matches!(inserter.insert(&["rowid", "a", "b"], vec![line(2), text(Some("3"))]), Err(AppError::Insert(_, 2))));
+        assert!(matches!(inserter.insert(&["a", "b"], texts(&["4", "5", "6"])), Err(AppError::Insert(_, 3))));
+    }
+
+    #[test]
+    fn test_skip_dropped_columns() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute("CREATE TABLE n (a text, b text, c text)", []).unwrap();
+
+        let mut inserter = Inserter::new(&tx, "n");
+        inserter.insert(&["rowid", "x", "c"], vec![line(1), text(Some("dropped")), text(Some("1"))]).unwrap();
+        inserter.finish().unwrap();
+
+        assert_eq!(rows(&tx), vec![(1, None, None, Some("1".to_owned()))]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use arrow::array::{Array, ArrayRef, AsArray, Float64Array, Int64Array, RecordBatchReader};
+use arrow::compute::{CastOptions, cast_with_options};
+use arrow::datatypes::{DataType, Float64Type, Int64Type};
+use arrow::ipc::reader::{FileReader, StreamReader};
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rusqlite:: Transaction;
+use rusqlite::types::Value;
+
+use crate::errors::{AppResult, AppResultU};
+use crate::types::Type;
+use crate::ui;
+use super::bulk::Inserter;
+
+
+
+const ARROW_MAGIC: &[u8] = b"ARROW1";
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+
+/// Parquet, Arrow IPC file or Arrow IPC stream
+pub struct Loader();
+
+enum Column {
+    Int(Int64Array),
+    Real(Float64Array),
+    Text(Vec<Option<String>>),
+}
+
+
+impl super::BinaryLoader for Loader {
+    fn load(&self, tx: &Transaction, source: &File, config: &super::Config) -> AppResultU {
+        let mut magic = vec![];
+        source.take(ARROW_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        (&*source).seek(SeekFrom::Start(0))?;
+
+        let reader: Box<dyn RecordBatchReader> = if magic.starts_with(PARQUET_MAGIC) {
+            Box::new(ParquetRecordBatchReaderBuilder::try_new(source.try_clone()?)?.build()?)
+        } else if magic.starts_with(ARROW_MAGIC) {
+            Box::new(FileReader::try_new(source.try_clone()?, None)?)
+        } else {
+            Box::new(StreamReader::try_new(BufReader::new(source.try_clone()?), None)?)
+        };
+
+        insert_batches(tx, reader, config)
+    }
+}
+
+
+/// Insert batch by batch (row group by row group for Parquet)
+fn insert_batches(tx: &Transaction, reader: Box<dyn RecordBatchReader>, config: &super::Config) -> AppResultU {
+    let schema = reader.schema();
+    let header: Vec<&str> = schema.fields().iter().map(|it| it.name().as_str()).collect();
+    let mut types: Vec<Type> = schema.fields().iter().map(|it| column_type(it.data_type())).collect();
+    let header = super::create_table(tx, "n", &header, &mut types, config)?;
+
+    let mut p = ui::Progress::new("load");
+    let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+    let mut inserter = Inserter::new(tx, "n");
+    for batch in reader {
+        let batch = batch?;
+        let columns = batch.columns().iter().zip(types.iter()).map(|(array, t)| Column::new(array, t)).collect::<AppResult<Vec<Column>>>()?;
+        for index in 0 .. batch.num_rows() {
+            p.progress();
+            let row: Vec<Value> = columns.iter().map(|it| it.value(index)).collect();
+            inserter.insert(&header, row)?;
+        }
+    }
+    inserter.finish()?;
+    p.complete();
+
+    Ok(())
+}
+
+fn column_type(t: &DataType) -> Type {
+    if t.is_integer() || *t == DataType::Boolean {
+        Type::Int
+    } else if t.is_floating() || t.is_decimal() {
+        Type::Real
+    } else {
+        Type::Text
+    }
+}
+
+
+impl Column {
+    fn new(array: &ArrayRef, t: &Type) -> AppResult<Self> {
+        // Fail on the values out of range (`UInt64` above `i64::MAX`) instead of making them NULL
+        let options = CastOptions { safe: false, ..CastOptions::default() };
+        let column = match *t {
+            Type::Int => Column::Int(cast_with_options(array, &DataType::Int64, &options)?.as_primitive::<Int64Type>().clone()),
+            Type::Real => Column::Real(cast_with_options(array, &DataType::Float64, &options)?.as_primitive::<Float64Type>().clone()),
+            Type::Text => {
+                let formatter = ArrayFormatter::try_new(array.as_ref(), &FormatOptions::default())?;
+                let values = (0 .. array.len()).map(|index| {
+                    if array.is_null(index) {
+                        None
+                    } else {
+                        Some(formatter.value(index).to_string())
+                    }
+                }).collect();
+                Column::Text(values)
+            }
+        };
+        Ok(column)
+    }
+
+    fn value(&self, index: usize) -> Value {
+        match *self {
+            Column::Int(ref array) if !array.is_null(index) => Value::Integer(array.value(index)),
+            Column::Real(ref array) if !array.is_null(index) => Value::Real(array.value(index)),
+            Column::Text(ref values) => values[index].clone().map_or(Value::Null, Value::Text),
+            _ => Value::Null,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::UInt64Array;
+
+    use super::*;
+
+    #[test]
+    fn test_uint64() {
+        let array: ArrayRef = Arc::new(UInt64Array::from(vec![Some(1), None]));
+        let t = column_type(array.data_type());
+        let column = Column::new(&array, &t).unwrap();
+        assert_eq!(column.value(0), Value::Integer(1));
+        assert_eq!(column.value(1), Value::Null);
+
+        let array: ArrayRef = Arc::new(UInt64Array::from(vec![u64::MAX]));
+        assert!(Column::new(&array, &t).is_err());
+    }
+}
@@ -1,118 +1,359 @@
 
-use quick_csv::Csv;
-use regex::Regex;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
 use rusqlite:: Transaction;
-use rusqlite::types::ToSql;
+use rusqlite::types::Value;
 
-use crate::db::TxExt;
-use crate::errors::{AppResult, AppResultU};
+use crate::errors::{AppError, AppResult, AppResultU};
 use crate::ui;
 use crate::types::*;
+use super::bulk::{self, Inserter};
+
+
 
+const OVERFLOW_COLUMN: &str = "overflow";
 
 
 pub struct Loader {
-    pub delimiter: Option<u8>,
+    pub dialect: Dialect,
+}
+
+/// Fields of a record with the line number where it starts
+pub struct Record {
+    /// Byte offset of the end in the source
+    pub end: usize,
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+pub struct Records<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    dialect: &'a Dialect,
+    line: usize,
+    /// Byte offset of `text` in the source
+    offset: usize,
+    text: &'a str,
 }
 
 
 impl super::Loader for Loader {
     fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
-        let mut content = open(&source, self.delimiter)?;
-        let header = content.nth(0).ok_or("Header not found")??;
+        let first = Records::new(source, &self.dialect).next().ok_or("Header not found")??;
         let header = if config.no_header {
-            let columns = header.len();
-            super::alpha_header(columns)
+            super::positional_header(first.fields.len(), config)
         } else {
-            let _ = content.next();
-            header.columns()?.collect::<Vec<&str>>()
+            first.fields
         };
+        let rows = || Records::new(source, &self.dialect).skip(if config.no_header { 0 } else { 1 });
+
         let mut types: Vec<Type> = vec![];
         types.resize(header.len(), Type::Int);
         if let Some(lines) = config.guess_lines {
-            let mut content = open(&source, self.delimiter)?;
-            content.next().ok_or("No header")??;
-            guess_types(&mut types, lines, content)?
+            guess_types(&mut types, lines, rows())?
         }
 
-        tx.create_table(&types, header.as_slice())?;
-        insert_rows(&tx, header.len(), content, &types)?;
+        let width = header.len();
+        let mut header = header;
+        if self.dialect.ragged == Ragged::Overflow {
+            header.push(OVERFLOW_COLUMN.to_owned());
+            types.push(Type::Text);
+        }
+
+        let columns = super::create_table(tx, "n", &header, &mut types, config)?;
+        let columns: Vec<&str> = columns.iter().map(AsRef::as_ref).collect();
+        insert_rows(tx, columns.as_slice(), width, source.len(), rows(), &types, &self.dialect)?;
         Ok(())
     }
 }
 
 
-fn guess_types(types: &mut Vec<Type>, lines: usize, rows: Csv<&[u8]>) -> AppResultU {
+impl<'a> Records<'a> {
+    /// Records from the header line, without the footer lines
+    pub fn new(source: &'a str, dialect: &'a Dialect) -> Self {
+        let header_line = dialect.header_line.max(1);
+        let mut text = source;
+        for _ in 1 .. header_line {
+            text = text.find('\n').map(|it| &text[it + 1 ..]).unwrap_or("");
+        }
+        let offset = source.len() - text.len();
+        for _ in 0 .. dialect.skip_footer {
+            let body = text.strip_suffix('\n').unwrap_or(text);
+            text = body.rfind('\n').map(|it| &body[.. it + 1]).unwrap_or("");
+        }
+
+        Records { chars: text.char_indices().peekable(), dialect, line: header_line, offset, text }
+    }
+
+    fn skip_line(&mut self) {
+        for (_, c) in self.chars.by_ref() {
+            if c == '\n' {
+                break;
+            }
+        }
+        self.line += 1;
+    }
+
+    fn field(&mut self) -> AppResult<String> {
+        let dialect = self.dialect;
+        let mut result = String::new();
+
+        if dialect.trim {
+            while let Some((_, ' ')) | Some((_, '\t')) = self.chars.peek() {
+                self.chars.next();
+            }
+        }
+
+        if let Some(&(_, c)) = self.chars.peek() {
+            if c == dialect.quote {
+                self.chars.next();
+                let start = self.line;
+                loop {
+                    match self.chars.next() {
+                        None => return Err(AppError::Csv("Unterminated quoted field".to_owned(), start)),
+                        Some((_, c)) if Some(c) == dialect.escape && c != dialect.quote => {
+                            match self.chars.next() {
+                                Some((_, c)) => {
+                                    self.line += (c == '\n') as usize;
+                                    result.push(c);
+                                },
+                                None => return Err(AppError::Csv("Unterminated quoted field".to_owned(), start)),
+                            }
+                        },
+                        Some((_, c)) if c == dialect.quote => {
+                            if self.chars.peek().map(|it| it.1) == Some(dialect.quote) {
+                                self.chars.next();
+                                result.push(c);
+                            } else {
+                                break;
+                            }
+                        },
+                        Some((_, c)) => {
+                            self.line += (c == '\n') as usize;
+                            result.push(c);
+                        },
+                    }
+                }
+                if dialect.trim {
+                    while let Some((_, ' ')) | Some((_, '\t')) = self.chars.peek() {
+                        self.chars.next();
+                    }
+                }
+            }
+        }
+
+        let quoted_len = result.len();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c == dialect.delimiter || c == '\n' {
+                break;
+            }
+            self.chars.next();
+            result.push(c);
+        }
+        if quoted_len < result.len() && result.ends_with('\r') {
+            result.pop();
+        }
+        if dialect.trim {
+            result.truncate(result.trim_end().len());
+        }
+
+        Ok(result)
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = AppResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let position = self.chars.peek()?.0;
+            let rest = &self.text[position ..];
+            let blank = rest.split('\n').next().is_none_or(|it| it.trim().is_empty());
+            let comment = self.dialect.comment.as_ref().is_some_and(|it| rest.starts_with(it.as_str()));
+            if !blank && !comment {
+                break;
+            }
+            self.skip_line();
+        }
+
+        let line = self.line;
+        let mut fields = vec![];
+        loop {
+            match self.field() {
+                Ok(field) => fields.push(field),
+                Err(err) => {
+                    self.chars = "".char_indices().peekable();
+                    return Some(Err(err));
+                },
+            }
+            match self.chars.next() {
+                Some((_, '\n')) => {
+                    self.line += 1;
+                    break;
+                },
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        let end = self.offset + self.chars.peek().map_or(self.text.len(), |it| it.0);
+        Some(Ok(Record { end, line, fields }))
+    }
+}
+
+
+fn guess_types<I: Iterator<Item = AppResult<Record>>>(types: &mut [Type], lines: usize, rows: I) -> AppResultU {
     if 0 == lines {
         return Ok(());
     }
 
-    let int = Regex::new("^[-+]?\\d{1,18}$")?;
-    let real = Regex::new("^[-+]?\\d+\\.\\d+$")?;
+    let guesser = super::TypeGuesser::new()?;
 
     for row in rows.take(lines) {
-        let row = row?;
-        let columns: Vec<&str> = row.columns()?.collect();
-        for (lv, column) in columns.iter().enumerate() {
-            let cleaned = column.replace(',', "");
-            let types = &mut types[lv];
-            if *types == Type::Int && !int.is_match(&cleaned) {
-                *types = Type::Real;
-            }
-            if *types == Type::Real && !real.is_match(&cleaned) {
-                *types = Type::Text;
-            }
+        for (t, column) in types.iter_mut().zip(&row?.fields) {
+            guesser.update(t, column);
         }
     }
 
     Ok(())
 }
 
-fn insert_rows(tx: &Transaction, headers: usize, rows: Csv<&[u8]>, types: &[Type]) -> AppResultU {
-    let insert = {
-        let mut insert = "INSERT INTO n VALUES(".to_owned();
-        let mut first = true;
-        for _ in 0..headers {
-            if first {
-                insert.push_str("?");
-                first = false;
-            } else {
-                insert.push_str(",?");
-            }
-        }
-        insert.push_str(")");
-        insert
-    };
+/// `width`: Number of the header fields, `total`: Size of the source
+fn insert_rows<I: Iterator<Item = AppResult<Record>>>(tx: &Transaction, columns: &[&str], width: usize, total: usize, rows: I, types: &[Type], dialect: &Dialect) -> AppResultU {
+    let columns = bulk::with_rowid(columns);
+    let mut inserter = Inserter::new(tx, "n");
+    let mut rejecter = Inserter::new(tx, super::REJECTED_TABLE);
 
-    let mut p = ui::Progress::new();
-    let mut stmt = tx.prepare(&insert)?;
+    let mut p = ui::Progress::with_total("load", total);
+    let mut rejected = 0;
     for row in rows {
         p.progress();
-        let row = row?;
-        let row: Vec<String> = row.columns()?.enumerate().map(|(index, it)| {
+        let Record { end, line, mut fields } = row?;
+        p.read(end);
+
+        let overflow = if fields.len() == width {
+            None
+        } else {
+            match dialect.ragged {
+                Ragged::Strict => return Err(ragged_error(fields.len(), width, line)),
+                Ragged::Pad if width < fields.len() => return Err(ragged_error(fields.len(), width, line)),
+                Ragged::Reject => {
+                    rejecter.insert(&["line", "fields"], vec![bulk::line(line), bulk::text(Some(serde_json::to_string(&fields)?))])?;
+                    rejected += 1;
+                    continue;
+                },
+                Ragged::Overflow if width < fields.len() =>
+                    Some(fields.split_off(width).join(&dialect.delimiter.to_string())),
+                _ => None,
+            }
+        };
+
+        let mut values: Vec<Value> = Some(bulk::line(line)).into_iter().chain(fields.into_iter().take(width).enumerate().map(|(index, it)| {
             use Type::*;
 
-            if let Some(t) = types.get(index) {
-                match t {
-                    Real | Int => it.replace(',', ""),
-                    _ => it.to_owned()
-                }
-            } else {
-                it.to_owned()
+            match types[index] {
+                Real | Int => Value::Text(it.replace(',', "")),
+                _ => Value::Text(it),
             }
-        }).collect();
-        let row: Vec<&dyn ToSql> = row.iter().map(|it| it as &dyn ToSql).collect();
-        stmt.execute(row.as_slice())?;
+        })).collect();
+        values.resize(width + 1, Value::Null);
+        if dialect.ragged == Ragged::Overflow {
+            values.push(bulk::text(overflow));
+        }
+
+        inserter.insert(&columns, values)?;
     }
+    inserter.finish()?;
+    rejecter.finish()?;
     p.complete();
 
+    if 0 < rejected {
+        eprintln!("rejected: {} rows into {}", rejected, super::REJECTED_TABLE);
+    }
+
     Ok(())
 }
 
-fn open<'a>(csv_text: &'a str, delimiter: Option<u8>) -> AppResult<Csv<&'a [u8]>> {
-    let mut csv = quick_csv::Csv::from_string(csv_text);
-    if let Some(delimiter) = delimiter {
-        csv = csv.delimiter(delimiter);
+fn ragged_error(fields: usize, width: usize, line: usize) -> AppError {
+    AppError::Csv(format!("{} fields, but the header has {} (see --ragged)", fields, width), line)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialect() -> Dialect {
+        Dialect {
+            delimiter: ',',
+            quote: '"',
+            escape: None,
+            comment: None,
+            trim: false,
+            header_line: 1,
+            skip_footer: 0,
+            ragged: Ragged::Strict,
+        }
+    }
+
+    fn records(source: &str, dialect: &Dialect) -> Vec<(usize, Vec<String>)> {
+        Records::new(source, dialect).map(|it| it.map(|it| (it.line, it.fields))).collect::<AppResult<_>>().unwrap()
+    }
+
+    fn row(line: usize, fields: &[&str]) -> (usize, Vec<String>) {
+        (line, fields.iter().map(|it| (*it).to_owned()).collect())
+    }
+
+    #[test]
+    fn test_quoted_newlines() {
+        assert_eq!(
+            records("a,b\n\"x\ny\",z\n3,4\n", &dialect()),
+            vec![row(1, &["a", "b"]), row(2, &["x\ny", "z"]), row(4, &["3", "4"])]);
+    }
+
+    #[test]
+    fn test_doubled_quotes() {
+        assert_eq!(
+            records("\"say \"\"hi\"\"\",\"\"\"\"\n", &dialect()),
+            vec![row(1, &["say \"hi\"", "\""])]);
+    }
+
+    #[test]
+    fn test_escape() {
+        let dialect = Dialect { escape: Some('\\'), ..dialect() };
+        assert_eq!(
+            records("\"a\\\"b\",\"c\\\\\"\n", &dialect),
+            vec![row(1, &["a\"b", "c\\"])]);
+    }
+
+    #[test]
+    fn test_crlf() {
+        assert_eq!(
+            records("a,\"b\"\r\n1,2\r\n", &dialect()),
+            vec![row(1, &["a", "b"]), row(2, &["1", "2"])]);
+    }
+
+    #[test]
+    fn test_header_line() {
+        let dialect = Dialect { header_line: 3, ..dialect() };
+        let source = "title\n\"x\",y\na,b\n1,2\n";
+        assert_eq!(records(source, &dialect), vec![row(3, &["a", "b"]), row(4, &["1", "2"])]);
+        assert_eq!(Records::new(source, &dialect).last().unwrap().unwrap().end, source.len());
+    }
+
+    #[test]
+    fn test_skip_footer() {
+        let dialect = Dialect { skip_footer: 1, ..dialect() };
+        assert_eq!(records("a,b\n1,2\ntotal,2\n", &dialect), vec![row(1, &["a", "b"]), row(2, &["1", "2"])]);
+        assert_eq!(records("a,b\n1,2\ntotal,2", &dialect), vec![row(1, &["a", "b"]), row(2, &["1", "2"])]);
+    }
+
+    #[test]
+    fn test_unterminated_quote() {
+        let dialect = dialect();
+        let mut records = Records::new("a\n\"x\n", &dialect);
+        assert!(records.next().unwrap().is_ok());
+        assert!(matches!(records.next(), Some(Err(AppError::Csv(_, 2)))));
+        assert!(records.next().is_none());
     }
-    Ok(csv)
 }
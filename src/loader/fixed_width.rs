@@ -0,0 +1,176 @@
+use rusqlite:: Transaction;
+
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::types::Type;
+use crate::ui;
+use super::bulk::{self, Inserter};
+
+
+
+/// Character range `start .. end` (0-based, `None` means end of line)
+type Range = (usize, Option<usize>);
+
+
+pub struct Loader {
+    pub ranges: Option<Vec<Range>>,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let first = source.lines().next().ok_or(AppError::Fixed("No lines"))?;
+        let sample: Vec<&str> = source.lines().skip(1).take(config.guess_lines.unwrap_or(100)).collect();
+
+        let ranges = match self.ranges {
+            Some(ref ranges) => ranges.clone(),
+            None => infer_ranges(first, &sample),
+        };
+
+        let header: Vec<String> = if config.no_header {
+            super::positional_header(ranges.len(), config)
+        } else {
+            split(first, &ranges).into_iter().map(Option::unwrap_or_default).collect()
+        };
+
+        let mut types = Type::new(header.len());
+        if let Some(lines) = config.guess_lines {
+            let guesser = super::TypeGuesser::new()?;
+            types.iter_mut().for_each(|it| *it = Type::Int);
+            let rows = source.lines().skip(if config.no_header { 0 } else { 1 }).take(lines);
+            for row in rows {
+                for (t, cell) in types.iter_mut().zip(split(row, &ranges)) {
+                    if let Some(cell) = cell {
+                        guesser.update(t, &cell);
+                    }
+                }
+            }
+        }
+
+        let header = super::create_table(tx, "n", &header, &mut types, config)?;
+        let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+        insert_rows(tx, source, header.as_slice(), &ranges, config.no_header)?;
+        Ok(())
+    }
+}
+
+impl Loader {
+    /// Parse 1-based inclusive ranges like `1-8,9-20,21-`
+    pub fn new(ranges: &Option<String>) -> AppResult<Self> {
+        let ranges = match *ranges {
+            Some(ref ranges) => Some(ranges.split(',').map(parse_range).collect::<AppResult<Vec<Range>>>()?),
+            None => None,
+        };
+        Ok(Loader { ranges })
+    }
+}
+
+
+fn parse_range(range: &str) -> AppResult<Range> {
+    let invalid = || AppError::Fixed("Invalid column range");
+    let number = |s: &str| s.trim().parse::<usize>().ok().filter(|it| 0 < *it).ok_or_else(invalid);
+
+    let mut parts = range.splitn(2, '-');
+    let start = number(parts.next().ok_or_else(invalid)?)?;
+    let end = match parts.next() {
+        Some(end) if end.trim().is_empty() => None,
+        Some(end) => Some(number(end)?),
+        None => Some(start),
+    };
+
+    if end.is_some_and(|it| it < start) {
+        return Err(invalid());
+    }
+
+    Ok((start - 1, end))
+}
+
+/// Columns start at header words.
+/// Each boundary is moved left into the gap between words, to the first position blank in every sample line,
+/// so that right-aligned values are not cut.
+fn infer_ranges(header: &str, sample: &[&str]) -> Vec<Range> {
+    let header: Vec<char> = header.chars().collect();
+    let sample: Vec<Vec<char>> = sample.iter().map(|it| it.chars().collect()).collect();
+    let is_blank = |position: usize| sample.iter().all(|line| line.get(position).is_none_or(|it| it.is_whitespace()));
+
+    let mut words = vec![];
+    for (position, c) in header.iter().enumerate() {
+        let previous_blank = position == 0 || header[position - 1].is_whitespace();
+        if !c.is_whitespace() && previous_blank {
+            words.push(position);
+        }
+    }
+
+    let mut starts = vec![0];
+    for &start in words.iter().skip(1) {
+        let gap_start = (0 .. start).rev().take_while(|it| header[*it].is_whitespace()).last().unwrap_or(start);
+        let boundary = (gap_start ..= start).find(|it| is_blank(*it)).unwrap_or(start);
+        starts.push(boundary);
+    }
+
+    let mut result = vec![];
+    for (index, start) in starts.iter().enumerate() {
+        result.push((*start, starts.get(index + 1).cloned()));
+    }
+    result
+}
+
+fn split(line: &str, ranges: &[Range]) -> Vec<Option<String>> {
+    let line: Vec<char> = line.chars().collect();
+
+    ranges.iter().map(|&(start, end)| {
+        let end = end.unwrap_or(line.len()).min(line.len());
+        if end <= start {
+            return None;
+        }
+        let cell: String = line[start .. end].iter().collect();
+        let cell = cell.trim();
+        if cell.is_empty() {
+            None
+        } else {
+            Some(cell.to_owned())
+        }
+    }).collect()
+}
+
+fn insert_rows(tx: &Transaction, content: &str, header: &[&str], ranges: &[Range], no_header: bool) -> AppResultU {
+    let columns = bulk::with_rowid(header);
+    let mut inserter = Inserter::new(tx, "n");
+    let mut p = ui::Progress::with_total("load", content.len());
+    let mut read = 0;
+
+    for (index, row) in content.lines().enumerate().skip(if no_header { 0 } else { 1 }) {
+        read += row.len() + 1;
+        p.read(read);
+        if row.trim().is_empty() {
+            continue;
+        }
+        p.progress();
+        let line = index + 1;
+        let row = Some(bulk::line(line)).into_iter().chain(split(row, ranges).into_iter().map(bulk::text)).collect();
+        inserter.insert(&columns, row)?;
+    }
+    inserter.finish()?;
+    p.complete();
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_ranges() {
+        let sample = [" 1  alice     10", "12  bob      200"];
+        let ranges = infer_ranges("id  name    score", &sample);
+        assert_eq!(ranges, vec![(0, Some(2)), (2, Some(9)), (9, None)]);
+        assert_eq!(split(sample[0], &ranges), vec![Some("1".to_owned()), Some("alice".to_owned()), Some("10".to_owned())]);
+        assert_eq!(split(sample[1], &ranges), vec![Some("12".to_owned()), Some("bob".to_owned()), Some("200".to_owned())]);
+    }
+
+    #[test]
+    fn test_infer_ranges_without_sample() {
+        assert_eq!(infer_ranges("a b", &[]), vec![(0, Some(1)), (1, None)]);
+    }
+}
@@ -0,0 +1,52 @@
+use rusqlite:: Transaction;
+use scraper::{ElementRef, Html, Selector};
+
+use crate::errors::{AppError, AppResult, AppResultU};
+
+use super::table::{self, Table};
+
+
+
+pub struct Loader {
+    pub index: Option<usize>,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        table::load_tables(tx, &tables(source)?, self.index, config)
+    }
+}
+
+
+/// Each `<table>`, its `<tr>` rows and their `<th>`/`<td>` cells
+fn tables(source: &str) -> AppResult<Vec<Table>> {
+    let selector = |s| Selector::parse(s).map_err(|_| AppError::Fixed("Invalid selector"));
+    let table = selector("table")?;
+    let tr = selector("tr")?;
+    let cell = selector("th, td")?;
+
+    let document = Html::parse_document(source);
+    let mut result = vec![];
+
+    for element in document.select(&table) {
+        let rows: Table = element.select(&tr)
+            .filter(|it| owner_table(*it) == Some(element))
+            .map(|row| row.select(&cell).map(text).collect())
+            .collect();
+        if !rows.is_empty() {
+            result.push(rows);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Nearest `<table>` ancestor, to skip the rows of nested tables
+fn owner_table(element: ElementRef) -> Option<ElementRef> {
+    element.ancestors().filter_map(ElementRef::wrap).find(|it| it.value().name() == "table")
+}
+
+fn text(element: ElementRef) -> String {
+    element.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
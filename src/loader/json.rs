@@ -1,145 +1,237 @@
-
-use std::collections::HashSet;
-use std::convert::AsRef;
+use std::collections::{HashMap, HashSet};
+use std::iter;
 
 use rusqlite:: Transaction;
-use rusqlite::types::ToSql;
 use serde_json::{Deserializer, Value, Map};
 
-use crate::db::TxExt;
-use crate::errors::{AppResult, AppResultU};
-use crate::sql;
-use crate::types::Type;
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::types::{Flatten, Type};
 use crate::ui;
+use super::bulk::{self, Inserter};
+use super::parallel::{self, Chunk};
 
 
 
-const NAME_DELIMITER: char =  '_';
-
-
 type ObjMap = Map<String, Value>;
 
 
-pub struct Loader();
+pub struct Loader {
+    pub flatten: Flatten,
+}
+
+/// A leaf of a flattened object
+pub struct Column {
+    pub path: Vec<String>,
+    pub name: String,
+    pub value: Option<String>,
+}
 
 
 impl super::Loader for Loader {
     fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
-        let header = header(&source, config.guess_lines.unwrap_or(100))?;
-        let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
-        let types = Type::new(header.len());
-        tx.create_table(&types, header.as_slice())?;
-        insert_rows(&tx, &source)?;
+        let names = create_table(tx, stream(source), config, &self.flatten)?;
+
+        let chunks = parallel::chunks(source, value_boundary());
+        let mut inserter = Inserter::new(tx, "n");
+        let mut p = ui::Progress::with_total("load", source.len());
+        let mut rowid = 0;
+        parallel::run(&chunks, config.jobs, |chunk| parse_chunk(chunk, &self.flatten), |chunk, rows| {
+            for (line, columns) in rows {
+                p.progress();
+                rowid = next_rowid(rowid, line);
+                insert_row(&mut inserter, rowid, columns, &names)?;
+            }
+            p.read(chunk.end);
+            Ok(())
+        })?;
+        inserter.finish()?;
+        p.complete();
+
         Ok(())
     }
 }
 
 
-fn header(content: &str, guess_lines: usize) -> AppResult<Vec<String>> {
-    let mut names = HashSet::<String>::new();
+/// Load a stream of objects with their line (or record) numbers, used by the loaders for JSON-like formats
+pub fn load_values<F, I>(tx: &Transaction, stream: F, config: &super::Config, flatten: &Flatten) -> AppResultU
+where F: Fn() -> I, I: Iterator<Item = AppResult<(usize, Value)>> {
+    let names = create_table(tx, stream(), config, flatten)?;
+    insert_rows(tx, stream(), flatten, &names)?;
+    Ok(())
+}
 
-    let stream = Deserializer::from_str(content).into_iter::<Value>();
-    let mut p = ui::Progress::new();
 
-    for it in stream {
-        if guess_lines < p.n {
-            break;
+impl Flatten {
+    /// Flatten the object into columns.
+    /// Sub-objects deeper than `max_depth` are kept as JSON text.
+    pub fn columns(&self, object: &ObjMap) -> AppResult<Vec<Column>> {
+        fn load_object(flatten: &Flatten, path: &mut Vec<String>, result: &mut Vec<Column>, object: &ObjMap) -> AppResultU {
+            for (n, v) in object.iter() {
+                path.push(n.to_owned());
+
+                let value = match *v {
+                    Value::Object(ref obj) if flatten.max_depth.is_none_or(|depth| path.len() <= depth) => {
+                        load_object(flatten, path, result, obj)?;
+                        path.pop();
+                        continue;
+                    }
+                    Value::Object(_) => Some(serde_json::to_string(v)?),
+                    Value::String(ref v) => Some(v.to_string()),
+                    Value::Number(ref v) => Some(format!("{}", v)),
+                    Value::Bool(ref v) => Some(if *v { "1" } else { "0" } .to_string()),
+                    _ => None,
+                };
+
+                result.push(Column { path: path.clone(), name: path.join(&flatten.delimiter), value });
+                path.pop();
+            }
+
+            Ok(())
         }
-        p.progress();
-        for name in column_names(&it?)? {
-            names.insert(name);
+
+        let mut result = Vec::<Column>::new();
+        load_object(self, &mut vec![], &mut result, object)?;
+
+        let mut names = HashSet::<&str>::new();
+        for column in &result {
+            if !names.insert(&column.name) {
+                return Err(AppError::NameCollision(column.name.clone()));
+            }
         }
+
+        Ok(result)
     }
+}
 
-    p.complete();
 
-    Ok(names.into_iter().collect())
+/// Values with the line numbers where they start
+fn stream(source: &str) -> impl Iterator<Item = AppResult<(usize, Value)>> + '_ {
+    let mut values = Deserializer::from_str(source).into_iter::<Value>();
+    let (mut line, mut counted) = (1, 0);
+
+    iter::from_fn(move || {
+        let start = source.len() - source[values.byte_offset() ..].trim_start().len();
+        line += source[counted .. start].matches('\n').count();
+        counted = start;
+        values.next().map(|it| it.map(|value| (line, value)).map_err(AppError::from))
+    })
 }
 
-fn column_names(value: &Value) -> AppResult<Vec<String>> {
-    fn load_object(prefix: &str, result: &mut Vec<String>, object: &ObjMap) -> AppResultU {
-        for (n, v) in object.iter() {
-            let mut new_prefix = prefix.to_string();
-            if !prefix.is_empty() {
-                new_prefix.push(NAME_DELIMITER);
-            }
-            new_prefix.push_str(n);
+/// Returns the sanitized column names by the flattened keys
+fn create_table<I: Iterator<Item = AppResult<(usize, Value)>>>(tx: &Transaction, stream: I, config: &super::Config, flatten: &Flatten) -> AppResult<HashMap<String, String>> {
+    let keys = header(stream, config.guess_lines.unwrap_or(100), flatten)?;
+    let mut types = Type::new(keys.len());
+    let header = super::create_table(tx, "n", &keys, &mut types, config)?;
+    Ok(keys.into_iter().zip(header).collect())
+}
 
-            match *v {
-                Value::Object(ref obj) => {
-                    load_object(&new_prefix, result, obj)?;
+/// Chunks can be split only after the lines where no value is open
+fn value_boundary() -> impl FnMut(&str) -> bool {
+    let (mut depth, mut string, mut escape) = (0isize, false, false);
+
+    move |line| {
+        for c in line.bytes() {
+            if string {
+                if escape {
+                    escape = false;
+                } else if c == b'\\' {
+                    escape = true;
+                } else if c == b'"' {
+                    string = false;
+                }
+            } else {
+                match c {
+                    b'"' => string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => depth -= 1,
+                    _ => (),
                 }
-                _ => result.push(new_prefix),
             }
         }
-
-        Ok(())
+        depth <= 0 && !string
     }
+}
 
-    let mut result = Vec::<String>::new();
-    if let Value::Object(ref obj) = *value {
-        load_object("", &mut result, obj)?;
+/// Flattened objects in the chunk with their line numbers
+fn parse_chunk(chunk: &Chunk, flatten: &Flatten) -> AppResult<Vec<(usize, Vec<Column>)>> {
+    let mut result = vec![];
+    for it in stream(chunk.text) {
+        let (line, value) = match it {
+            Ok(it) => it,
+            // Parse again after the lines before the chunk, to report the error at the line in the source
+            Err(_) => return Err(stream(&("\n".repeat(chunk.line - 1) + chunk.text)).find_map(Result::err).expect("BUG")),
+        };
+        if let Value::Object(ref obj) = value {
+            result.push((chunk.line + line - 1, flatten.columns(obj)?));
+        }
     }
     Ok(result)
 }
 
-fn insert_rows(tx: &Transaction, content: &str) -> AppResultU {
-    let stream = Deserializer::from_str(content).into_iter::<Value>();
+fn header<I: Iterator<Item = AppResult<(usize, Value)>>>(stream: I, guess_lines: usize, flatten: &Flatten) -> AppResult<Vec<String>> {
+    let mut names = HashMap::<String, Vec<String>>::new();
+
+    let mut p = ui::Progress::new("header");
 
-    let mut p = ui::Progress::new();
     for it in stream {
+        if guess_lines < p.n {
+            break;
+        }
         p.progress();
-        if let Value::Object(ref obj) = it? {
-            insert_row(tx, obj)?;
+        if let (_, Value::Object(ref obj)) = it? {
+            for column in flatten.columns(obj)? {
+                if let Some(path) = names.get(&column.name) {
+                    if *path != column.path {
+                        return Err(AppError::NameCollision(column.name));
+                    }
+                    continue;
+                }
+                names.insert(column.name, column.path);
+            }
         }
     }
+
     p.complete();
 
-    Ok(())
+    Ok(names.into_keys().collect())
 }
 
-pub fn insert_row(tx: &Transaction, obj: &ObjMap) -> AppResultU {
-    fn load_object(prefix: &str, names: &mut String, values: &mut String, args: &mut Vec<String>, object: &ObjMap) -> AppResultU {
-        for (n, v) in object.iter() {
-            let mut new_prefix = prefix.to_string();
-            if !prefix.is_empty() {
-                new_prefix.push(NAME_DELIMITER);
-            }
-            new_prefix.push_str(n);
-
-            let arg = match *v {
-                Value::Object(ref obj) => {
-                    load_object(&new_prefix, names, values, args, obj)?;
-                    continue;
-                }
-                Value::String(ref v) => v.to_string(),
-                Value::Number(ref v) => format!("{}", v),
-                Value::Bool(ref v) => if *v { "1" } else { "0" } .to_string(),
-                _ => continue,
-            };
-
-            if !names.is_empty() {
-                names.push(',');
-                values.push(',');
-            }
-
-            names.push_str(&sql::quote_string(&new_prefix));
-            values.push('?');
-            args.push(arg);
+fn insert_rows<I: Iterator<Item = AppResult<(usize, Value)>>>(tx: &Transaction, stream: I, flatten: &Flatten, names: &HashMap<String, String>) -> AppResultU {
+    let mut inserter = Inserter::new(tx, "n");
+    let mut p = ui::Progress::new("load");
+    let mut rowid = 0;
+    for it in stream {
+        p.progress();
+        if let (line, Value::Object(ref obj)) = it? {
+            rowid = next_rowid(rowid, line);
+            insert_row(&mut inserter, rowid, flatten.columns(obj)?, names)?;
         }
-
-        Ok(())
     }
+    inserter.finish()?;
+    p.complete();
 
-    let mut names = String::new();
-    let mut values = String::new();
-    let mut args = Vec::<String>::new();
+    Ok(())
+}
 
-    load_object("", &mut names, &mut values, &mut args, obj)?;
+/// The line number, or the next free rowid for the values starting on the same line as the previous one
+fn next_rowid(previous: usize, line: usize) -> usize {
+    line.max(previous + 1)
+}
 
-    let q = format!("INSERT INTO n ({}) VALUES ({})", names, values);
-    let args: Vec<&dyn ToSql> = args.iter().map(|it| it as &dyn ToSql).collect();
-    tx.execute(&q, &*args)?;
+/// `names`: Sanitized column names of the flattened keys
+fn insert_row(inserter: &mut Inserter, line: usize, columns: Vec<Column>, names: &HashMap<String, String>) -> AppResultU {
+    let mut targets = vec!["rowid".to_owned()];
+    let mut values = vec![bulk::line(line)];
+
+    for column in columns {
+        let arg = match column.value {
+            Some(arg) => arg,
+            None => continue,
+        };
+        targets.push(names.get(&column.name).cloned().ok_or(AppError::UnknownColumn(column.name))?);
+        values.push(bulk::text(Some(arg)));
+    }
 
-    Ok(())
+    let targets: Vec<&str> = targets.iter().map(AsRef::as_ref).collect();
+    inserter.insert(&targets, values)
 }
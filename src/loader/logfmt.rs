@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use rusqlite:: Transaction;
+
+use crate::errors::{AppResult, AppResultU};
+use crate::types::Type;
+use crate::ui;
+use super::bulk::{self, Inserter};
+use super::parallel;
+
+
+
+pub struct Loader();
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let (keys, mut types) = header(source, config.guess_lines)?;
+        let header = super::create_table(tx, "n", &keys, &mut types, config)?;
+        let names: HashMap<&str, &str> = keys.into_iter().zip(header.iter().map(AsRef::as_ref)).collect();
+        insert_rows(tx, source, &names, config.jobs)?;
+        Ok(())
+    }
+}
+
+
+/// Keys in order of appearance, and their types guessed from the values in the first `guess_lines`
+fn header(content: &str, guess_lines: Option<usize>) -> AppResult<(Vec<&str>, Vec<Type>)> {
+    let guesser = super::TypeGuesser::new()?;
+    let mut names = HashSet::<&str>::new();
+    let mut header = vec![];
+    let mut types = vec![];
+    let mut guessed = vec![];
+
+    for (index, row) in content.lines().enumerate() {
+        let guess = guess_lines.is_none_or(|it| index < it);
+        for (name, value) in parse(row) {
+            if names.insert(name) {
+                header.push(name);
+                types.push(Type::Int);
+                guessed.push(false);
+            }
+            if let (true, Some(value)) = (guess, value) {
+                let position = header.iter().position(|it| *it == name).expect("BUG");
+                guesser.update(&mut types[position], &value);
+                guessed[position] = true;
+            }
+        }
+    }
+
+    for (t, guessed) in types.iter_mut().zip(guessed) {
+        if !guessed {
+            *t = Type::Text;
+        }
+    }
+
+    Ok((header, types))
+}
+
+/// `names`: Sanitized column names of the keys
+fn insert_rows<'a>(tx: &Transaction, content: &'a str, names: &HashMap<&str, &str>, jobs: usize) -> AppResultU {
+    let mut inserter = Inserter::new(tx, "n");
+    let mut p = ui::Progress::with_total("load", content.len());
+
+    let chunks = parallel::line_chunks(content);
+    let parse_chunk = |chunk: &parallel::Chunk<'a>| Ok(chunk.lines().map(|(line, row)| (line, parse(row))).collect::<Vec<_>>());
+    parallel::run(&chunks, jobs, parse_chunk, |chunk, rows| {
+        for (line, pairs) in rows {
+            if pairs.is_empty() {
+                continue;
+            }
+            p.progress();
+
+            let mut columns = vec!["rowid"];
+            let mut values = vec![bulk::line(line)];
+
+            // The last value of a repeated key wins
+            for (name, value) in pairs {
+                let column = names[name];
+                let value = bulk::text(value);
+                match columns.iter().position(|it| *it == column) {
+                    Some(position) => values[position] = value,
+                    None => {
+                        columns.push(column);
+                        values.push(value);
+                    }
+                }
+            }
+
+            inserter.insert(&columns, values)?;
+        }
+        p.read(chunk.end);
+        Ok(())
+    })?;
+    inserter.finish()?;
+
+    p.complete();
+
+    Ok(())
+}
+
+/// Parse `key=value key="quoted \"value\"" flag` pairs.
+/// A bare key has no value.
+fn parse(line: &str) -> Vec<(&str, Option<String>)> {
+    let mut result = vec![];
+    let mut chars = line.char_indices().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+        let start = match chars.peek() {
+            Some(&(index, _)) => index,
+            None => break,
+        };
+
+        let mut end = line.len();
+        let mut has_value = false;
+        while let Some(&(index, c)) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                end = index;
+                has_value = c == '=';
+                chars.next();
+                break;
+            }
+            chars.next();
+        }
+
+        let value = if has_value { Some(parse_value(&mut chars)) } else { None };
+        let key = &line[start .. end];
+        if !key.is_empty() {
+            result.push((key, value));
+        }
+    }
+
+    result
+}
+
+fn parse_value(chars: &mut Peekable<CharIndices>) -> String {
+    let mut value = String::new();
+
+    if chars.peek().is_some_and(|(_, c)| *c == '"') {
+        chars.next();
+        while let Some((_, c)) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+    } else {
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            value.push(c);
+            chars.next();
+        }
+    }
+
+    value
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(" level=info msg=\"say \\\"hi\\\"\\n\" flag empty= "),
+            vec![
+                ("level", Some("info".to_owned())),
+                ("msg", Some("say \"hi\"\n".to_owned())),
+                ("flag", None),
+                ("empty", Some("".to_owned())),
+            ]);
+    }
+
+    #[test]
+    fn test_parse_empty_key() {
+        assert_eq!(parse("=x a=1"), vec![("a", Some("1".to_owned()))]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_repeated_key() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute("CREATE TABLE n (a text, b text)", []).unwrap();
+
+        let names: HashMap<&str, &str> = vec![("a", "a"), ("b", "b")].into_iter().collect();
+        insert_rows(&tx, "a=1 b=2 a=3\n", &names, 1).unwrap();
+
+        let row: (String, String) = tx.query_row("SELECT a, b FROM n", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(row, ("3".to_owned(), "2".to_owned()));
+    }
+}
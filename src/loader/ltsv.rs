@@ -1,13 +1,13 @@
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use rusqlite::types::ToSql;
 use rusqlite:: Transaction;
 
-use crate::db::TxExt;
-use crate::errors::{AppResult, AppResultU};
+use crate::errors::{AppError, AppResult, AppResultU};
 use crate::types::Type;
 use crate::ui;
+use super::bulk::{self, Inserter};
+use super::parallel;
 
 
 
@@ -15,73 +15,88 @@ pub struct Loader();
 
 
 impl super::Loader for Loader {
-    fn load(&self, tx: &Transaction, source: &str, _: &super::Config) -> AppResultU {
-        let header = header(&source)?;
-        let types = Type::new(header.len());
-        tx.create_table(&types, header.as_slice())?;
-        insert_rows(&tx, &source)?;
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let keys = header(source)?;
+        let mut types = Type::new(keys.len());
+        let header = super::create_table(tx, "n", &keys, &mut types, config)?;
+        let names: HashMap<&str, &str> = keys.into_iter().zip(header.iter().map(AsRef::as_ref)).collect();
+        insert_rows(tx, source, &names, config.jobs)?;
         Ok(())
     }
 }
 
 
+/// Labels in order of appearance
 fn header(content: &str) -> AppResult<Vec<&str>> {
     let mut names = HashSet::<&str>::new();
+    let mut result = vec![];
 
     for row in content.lines() {
-        for column in row.split('\t') {
-            if let Some(idx) = column.find(':') {
-                if idx == 0 {
-                    continue;
-                }
-                let (name, _) = column.split_at(idx);
-                if !names.contains(name) {
-                    names.insert(name);
-                }
+        for (name, _) in parse(row) {
+            if names.insert(name) {
+                result.push(name);
             }
         }
     }
 
-    Ok(names.into_iter().collect())
+    Ok(result)
 }
 
-fn insert_rows(tx: &Transaction, content: &str) -> AppResultU {
-    let mut p = ui::Progress::new();
-
-    for row in content.lines() {
-        p.progress();
+/// `names`: Sanitized column names of the keys
+fn insert_rows<'a>(tx: &Transaction, content: &'a str, names: &HashMap<&str, &str>, jobs: usize) -> AppResultU {
+    let mut inserter = Inserter::new(tx, "n");
+    let mut p = ui::Progress::with_total("load", content.len());
+
+    let chunks = parallel::line_chunks(content);
+    let parse_chunk = |chunk: &parallel::Chunk<'a>| Ok(chunk.lines().map(|(line, row)| (line, parse(row))).collect::<Vec<_>>());
+    parallel::run(&chunks, jobs, parse_chunk, |chunk, rows| {
+        for (line, pairs) in rows {
+            if pairs.is_empty() {
+                continue;
+            }
+            p.progress();
+
+            let mut columns = vec!["rowid"];
+            let mut values = vec![bulk::line(line)];
+
+            // The last value of a repeated label wins
+            for (name, value) in pairs {
+                let column = *names.get(name).ok_or_else(|| AppError::UnknownColumn(name.to_owned()))?;
+                let value = bulk::text(Some(value));
+                match columns.iter().position(|it| *it == column) {
+                    Some(position) => values[position] = value,
+                    None => {
+                        columns.push(column);
+                        values.push(value);
+                    }
+                }
+            }
 
-        let mut names = String::new();
-        let mut values = String::new();
-        let mut args = Vec::<&str>::new();
+            inserter.insert(&columns, values)?;
+        }
+        p.read(chunk.end);
+        Ok(())
+    })?;
+    inserter.finish()?;
 
-        for (index, column) in row.split('\t').enumerate() {
-            if let Some(idx) = column.find(':') {
-                if idx == 0 && column.len() <= 1{
-                    continue;
-                }
-                let (name, value) = column.split_at(idx);
-                let value = &value[1..];
+    p.complete();
 
-                if 0 < index {
-                    names.push(',');
-                    values.push(',');
-                }
+    Ok(())
+}
 
-                let name = name.replace("'", "''");
-                names.push_str(&format!("'{}'", name));
-                values.push('?');
+/// `label:value` pairs, without the fields of empty labels
+fn parse(row: &str) -> Vec<(&str, &str)> {
+    let mut result = vec![];
 
-                args.push(value);
+    for column in row.split('\t') {
+        if let Some(idx) = column.find(':') {
+            if idx == 0 {
+                continue;
             }
+            let (name, value) = column.split_at(idx);
+            result.push((name, &value[1..]));
         }
-
-        let q = format!("INSERT INTO n ({}) VALUES ({})", names, values);
-        let args: Vec<&dyn ToSql> = args.iter().map(|it| it as &dyn ToSql).collect();
-        tx.execute(&q, &*args)?;
     }
 
-    p.complete();
-
-    Ok(())
+    result
 }
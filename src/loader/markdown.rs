@@ -0,0 +1,74 @@
+use regex::Regex;
+use rusqlite:: Transaction;
+
+use crate::errors::{AppResult, AppResultU};
+
+use super::table::{self, Table};
+
+
+
+pub struct Loader {
+    pub index: Option<usize>,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        table::load_tables(tx, &tables(source)?, self.index, config)
+    }
+}
+
+
+/// Pipe tables: a header row, a delimiter row (`|---|:---:|`) and body rows
+fn tables(source: &str) -> AppResult<Vec<Table>> {
+    let delimiter = Regex::new(r"^\|?\s*:?-+:?\s*(\|\s*:?-+:?\s*)*\|?$")?;
+
+    let mut result = vec![];
+    let mut lines = source.lines().map(str::trim).peekable();
+
+    while let Some(line) = lines.next() {
+        let is_header = line.contains('|') && lines.peek().is_some_and(|it| delimiter.is_match(it));
+        if !is_header {
+            continue;
+        }
+        lines.next();
+
+        let mut table = vec![cells(line)];
+        while let Some(line) = lines.peek() {
+            if line.is_empty() || !line.contains('|') {
+                break;
+            }
+            table.push(cells(line));
+            lines.next();
+        }
+        result.push(table);
+    }
+
+    Ok(result)
+}
+
+fn cells(line: &str) -> Vec<String> {
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = if line.ends_with('|') && !line.ends_with("\\|") { &line[.. line.len() - 1] } else { line };
+
+    let mut result = vec![];
+    let mut cell = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('|') => cell.push('|'),
+                Some(c) => {
+                    cell.push('\\');
+                    cell.push(c);
+                }
+                None => cell.push('\\'),
+            },
+            '|' => result.push(std::mem::take(&mut cell).trim().to_owned()),
+            c => cell.push(c),
+        }
+    }
+    result.push(cell.trim().to_owned());
+
+    result
+}
@@ -1,44 +1,326 @@
+use std::collections::HashSet;
+use std::fs::File;
+
 
 use rusqlite::Transaction;
+use rusqlite::functions::FunctionFlags;
+
+use ::regex::Regex as Pattern;
 
-use crate::errors::AppResultU;
+use crate::db::TxExt;
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::sql;
+use crate::types::{Naming, Schema, Type};
 
+pub mod bulk;
+mod columnar;
 mod csv;
+mod fixed_width;
+mod html;
 mod json;
+mod logfmt;
 mod ltsv;
+mod markdown;
+mod parallel;
 mod simple;
 mod regex;
+mod spreadsheet;
+mod table;
+mod toml;
+mod xml;
+mod yaml;
 
+pub use columnar::{Loader as Columnar};
 pub use csv::{Loader as Csv};
+pub use fixed_width::{Loader as FixedWidth};
+pub use html::{Loader as Html};
 pub use json::{Loader as Json};
+pub use logfmt::{Loader as Logfmt};
 pub use ltsv::{Loader as Ltsv};
+pub use markdown::{Loader as Markdown};
 pub use self::regex::{Loader as Regex};
 pub use simple::{Loader as Simple};
+pub use spreadsheet::{Loader as Spreadsheet};
+pub use self::toml::{Loader as Toml};
+pub use xml::{Loader as Xml};
+pub use yaml::{Loader as Yaml};
+
+
 
+/// Side table for the rows that could not be loaded into `n`
+pub const REJECTED_TABLE: &str = "n_rejected";
+/// Files of a glob or archive members loaded so far
+pub const MERGED_TABLE: &str = "n_merged";
+pub const FTS_TABLE: &str = "n_fts";
+/// Tables written by nq, which loaded sheets and archive members must not replace
+pub const RESERVED_TABLES: &[&str] = &["n", "meta", "files", FTS_TABLE, MERGED_TABLE, REJECTED_TABLE];
 
+const FILTER_TRIGGER_PREFIX: &str = "nq_filter_";
 
+
+
+#[derive(Clone, Debug)]
 pub struct Config {
+    /// Projection applied to `n`
+    pub columns: Option<Vec<String>>,
+    /// SQL expression or `/REGEX/`
+    pub filter: Option<String>,
+    /// Columns of `n` indexed by the full-text search table
+    pub fts: Option<Vec<String>>,
     pub guess_lines: Option<usize>,
+    /// Indexes created on `n` after loading
+    pub indexes: Vec<Vec<String>>,
+    /// Number of the threads parsing the source
+    pub jobs: usize,
+    pub member_tables: bool,
+    pub naming: Naming,
     pub no_header: bool,
+    pub primary_key: Option<Vec<String>>,
+    pub schema: Schema,
+    pub snake_case: bool,
+}
+
+pub struct TypeGuesser {
+    int: Pattern,
+    real: Pattern,
 }
 
 pub trait Loader {
     fn load(&self, tx: &Transaction, source: &str, config: &Config) -> AppResultU;
 }
 
+pub trait BinaryLoader {
+    fn load(&self, tx: &Transaction, source: &File, config: &Config) -> AppResultU;
+}
+
+
+impl Config {
+    /// Without `jobs`, which does not change the loaded content, and the indexes and FTS table built after loading
+    pub fn to_sql_literal(&self) -> String {
+        format!("{:?}", Config { fts: None, indexes: vec![], jobs: 0, primary_key: None, ..self.clone() })
+    }
+}
+
+impl TypeGuesser {
+    pub fn new() -> AppResult<Self> {
+        Ok(TypeGuesser {
+            int: Pattern::new("^[-+]?\\d{1,18}$")?,
+            real: Pattern::new("^[-+]?\\d+\\.\\d+$")?,
+        })
+    }
+
+    /// Narrow the type `t` to accept `value`
+    pub fn update(&self, t: &mut Type, value: &str) {
+        let cleaned = value.replace(',', "");
+        if *t == Type::Int && !self.int.is_match(&cleaned) {
+            *t = Type::Real;
+        }
+        if *t == Type::Real && !self.real.is_match(&cleaned) {
+            *t = Type::Text;
+        }
+    }
+}
+
+
+/// `RESERVED_TABLES`, the FTS5 shadow tables and the internal `sqlite_*` tables, in any case as SQLite
+pub fn is_reserved_table(name: &str) -> bool {
+    let name = name.to_lowercase();
+    RESERVED_TABLES.contains(&name.as_str()) || name.starts_with(&format!("{}_", FTS_TABLE)) || name.starts_with("sqlite_")
+}
+
+/// Names of the columns without header
+pub fn positional_header(n: usize, config: &Config) -> Vec<String> {
+    (0 .. n).map(|index| positional_name(index, config.naming)).collect()
+}
+
+/// `a` .. `z`, `aa`, `ab` .. (`Naming::Alpha`) or `c1`, `c2` .. (`Naming::Numbered`) for the 0-based `index`
+pub fn positional_name(index: usize, naming: Naming) -> String {
+    match naming {
+        Naming::Alpha => {
+            let mut result = vec![];
+            let mut n = index + 1;
+            while 0 < n {
+                n -= 1;
+                result.push(b'a' + (n % 26) as u8);
+                n /= 26;
+            }
+            result.reverse();
+            String::from_utf8(result).expect("BUG")
+        },
+        Naming::Numbered => format!("c{}", index + 1),
+    }
+}
+
+/// Create `table` with the sanitized header, after applying the annotations in the header (`age:int`) and `config.schema`.
+/// The columns named differently from `header` are recorded in `meta` as `header:TABLE`.
+/// Returns the column names in the order of `header`, including the ones dropped by `config.columns` (skipped by `Inserter`).
+pub fn create_table<S: AsRef<str>>(tx: &Transaction, table: &str, header: &[S], types: &mut [Type], config: &Config) -> AppResult<Vec<String>> {
+    let original = header;
+    let mut header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+    for (name, t) in header.iter_mut().zip(types.iter_mut()) {
+        if let Some((stripped, annotated)) = name.rsplit_once(':').and_then(|(it, t)| Some((it, Type::parse(t)?))) {
+            *name = stripped;
+            *t = annotated;
+        }
+    }
+
+    let mut names = sanitize_header(&header, config);
 
-pub fn alpha_header(n: usize) -> Vec<&'static str> {
-    const ALPHAS: &[&str] = &[
-        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p",
-        "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "A", "B", "C", "D", "E", "F",
-        "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V",
-        "W", "X", "Y", "Z"
-    ];
+    for (column, t) in &config.schema.types {
+        match names.iter().position(|it| it == column) {
+            Some(index) => types[index] = t.clone(),
+            None => eprintln!("Skip unknown column: {}", column),
+        }
+    }
+
+    for (old, new) in &config.schema.renames {
+        if names.iter().any(|it| it != old && it.eq_ignore_ascii_case(new)) {
+            return Err(AppError::NameCollision(new.to_owned()));
+        }
+        match names.iter().position(|it| it == old) {
+            Some(index) => names[index] = new.to_owned(),
+            None => eprintln!("Skip unknown column: {}", old),
+        }
+    }
+
+    let renamed: Vec<(&str, &str)> = original.iter().map(AsRef::as_ref).zip(names.iter().map(AsRef::as_ref)).filter(|(original, name)| original != name).collect();
+    tx.set_meta(&format!("header:{}", table), &serde_json::to_string(&renamed)?)?;
+
+    let created = projection(table, &names, config)?;
+    let columns: Vec<&str> = created.iter().map(|it| names[*it].as_str()).collect();
+    let created_types: Vec<Type> = created.iter().map(|it| types[*it].clone()).collect();
+    tx.create_named_table(table, &created_types, columns.as_slice())?;
+    if let Some(ref filter) = config.filter {
+        create_filter(tx, table, columns.as_slice(), filter)?;
+    }
+
+    Ok(names)
+}
+
+/// Indexes of the columns of `names` to create: `config.columns` for `n`, in that order,
+/// followed by the other columns the filter may refer to (dropped by `finish_tables`)
+fn projection(table: &str, names: &[String], config: &Config) -> AppResult<Vec<usize>> {
+    let columns = match config.columns {
+        Some(ref columns) if table == "n" => columns,
+        _ => return Ok((0 .. names.len()).collect()),
+    };
 
     let mut result = vec![];
-    for alpha in ALPHAS.iter().take(n) {
-        result.push(*alpha);
+    for column in columns {
+        result.push(names.iter().position(|it| it == column).ok_or_else(|| AppError::UnknownColumn(column.to_owned()))?);
     }
+
+    if let Some(ref filter) = config.filter {
+        // The regular expression is searched in all the fields
+        let regex = regex_filter(filter).is_some();
+        let filter = filter.to_lowercase();
+        for (index, name) in names.iter().enumerate() {
+            if !result.contains(&index) && (regex || filter.contains(&name.to_lowercase())) {
+                result.push(index);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Skip the inserted rows not matching `filter` by a trigger, dropped by `finish_tables` after loading
+fn create_filter(tx: &Transaction, table: &str, columns: &[&str], filter: &str) -> AppResultU {
+    let condition = match regex_filter(filter) {
+        Some(pattern) => {
+            let pattern = Pattern::new(pattern)?;
+            tx.create_scalar_function("nq_search", 1, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, move |context| {
+                Ok(pattern.is_match(&context.get::<String>(0)?))
+            })?;
+            let fields: Vec<String> = columns.iter().map(|it| format!("coalesce(NEW.{}, '')", sql::quote_identifier(it))).collect();
+            format!("nq_search({})", fields.join(" || char(9) || "))
+        },
+        None => {
+            let fields: Vec<String> = columns.iter().map(|it| format!("NEW.{} AS {}", sql::quote_identifier(it), sql::quote_identifier(it))).collect();
+            format!("EXISTS (SELECT 1 FROM (SELECT {}) WHERE {})", fields.join(","), filter)
+        },
+    };
+
+    tx.execute(
+        &format!(
+            "CREATE TEMP TRIGGER {} BEFORE INSERT ON {} WHEN NOT ({}) BEGIN SELECT RAISE(IGNORE); END",
+            sql::quote_identifier(&format!("{}{}", FILTER_TRIGGER_PREFIX, table)), sql::quote_identifier(table), condition),
+        [])?;
+
+    Ok(())
+}
+
+/// `PATTERN` of `/PATTERN/`
+fn regex_filter(filter: &str) -> Option<&str> {
+    filter.strip_prefix('/').and_then(|it| it.strip_suffix('/'))
+}
+
+/// Drop the filters, and the columns of `n` kept only for them
+pub fn finish_tables(tx: &Transaction, config: &Config) -> AppResultU {
+    let triggers = {
+        let mut stmt = tx.prepare("SELECT name FROM sqlite_temp_master WHERE type = 'trigger'")?;
+        let names = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+        names
+    };
+    for trigger in triggers.iter().filter(|it| it.starts_with(FILTER_TRIGGER_PREFIX)) {
+        tx.execute(&format!("DROP TRIGGER {}", sql::quote_identifier(trigger)), [])?;
+    }
+
+    if let Some(ref columns) = config.columns {
+        let existing: Vec<String> = tx.columns("n")?.into_iter().map(|(name, _)| name).collect();
+        if existing != *columns {
+            tx.project_table("n", columns)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Trim the names, fill the empty ones, make them unique (`id`, `id_2`) and optionally snake_case them
+fn sanitize_header<S: AsRef<str>>(header: &[S], config: &Config) -> Vec<String> {
+    let mut used = HashSet::<String>::new();
+    let mut result = vec![];
+
+    for (index, original) in header.iter().map(AsRef::as_ref).enumerate() {
+        let mut base = original.trim().to_owned();
+        if config.snake_case {
+            base = snake_case(&base);
+        }
+        if base.is_empty() {
+            base = positional_name(index, config.naming);
+        }
+
+        let mut name = base.clone();
+        let mut n = 1;
+        while !used.insert(name.to_lowercase()) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+
+        result.push(name);
+    }
+
+    result
+}
+
+/// `userId`, `User ID` -> `user_id`
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous: Option<char> = None;
+
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            if c.is_uppercase() && previous.is_some_and(|it| it.is_lowercase() || it.is_numeric()) {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        } else if !result.is_empty() && !result.ends_with('_') {
+            result.push('_');
+        }
+        previous = Some(c);
+    }
+
+    result.truncate(result.trim_end_matches('_').len());
     result
 }
 
@@ -54,6 +336,93 @@ pub fn qs(n: usize) -> String {
     result
 }
 
-pub fn insert_values(n: usize) -> String {
-    format!("INSERT INTO n VALUES({})", qs(n))
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positional_name() {
+        let alpha: Vec<String> = [0, 1, 25, 26, 27, 51, 52, 701, 702].iter().map(|it| positional_name(*it, Naming::Alpha)).collect();
+        assert_eq!(alpha, vec!["a", "b", "z", "aa", "ab", "az", "ba", "zz", "aaa"]);
+        assert_eq!(positional_name(0, Naming::Numbered), "c1");
+        assert_eq!(positional_name(9, Naming::Numbered), "c10");
+    }
+
+    fn config() -> Config {
+        Config {
+            columns: None,
+            filter: None,
+            fts: None,
+            guess_lines: None,
+            indexes: vec![],
+            jobs: 1,
+            member_tables: false,
+            naming: Naming::Alpha,
+            no_header: false,
+            primary_key: None,
+            schema: Schema::default(),
+            snake_case: false,
+        }
+    }
+
+    #[test]
+    fn test_sanitize_header() {
+        assert_eq!(sanitize_header(&[" id ", "", "ID", "id", "name"], &config()), vec!["id", "b", "ID_2", "id_3", "name"]);
+
+        let config = Config { snake_case: true, naming: Naming::Numbered, ..config() };
+        assert_eq!(sanitize_header(&["userId", "User ID", "--", "Total (JPY)"], &config), vec!["user_id", "user_id_2", "c3", "total_jpy"]);
+    }
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("userId"), "user_id");
+        assert_eq!(snake_case("User ID"), "user_id");
+        assert_eq!(snake_case("item2Name"), "item2_name");
+        assert_eq!(snake_case("  a--b  "), "a_b");
+    }
+
+    #[test]
+    fn test_is_reserved_table() {
+        assert!(is_reserved_table("Meta"));
+        assert!(is_reserved_table("n_fts_data"));
+        assert!(is_reserved_table("SQLITE_sequence"));
+        assert!(!is_reserved_table("sheet1"));
+    }
+
+    #[test]
+    fn test_create_table_schema() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        let schema = Schema {
+            renames: vec![("name".to_owned(), "full_name".to_owned())],
+            types: vec![("age".to_owned(), Type::Real)],
+        };
+        let config = Config { schema, ..config() };
+
+        let mut types = Type::new(3);
+        let names = create_table(&tx, "n", &["id:int", "name", "age"], &mut types, &config).unwrap();
+        assert_eq!(names, vec!["id", "full_name", "age"]);
+        assert_eq!(types, vec![Type::Int, Type::Text, Type::Real]);
+
+        let columns = tx.columns("n").unwrap();
+        assert_eq!(columns, vec![
+            ("id".to_owned(), "INTEGER".to_owned()),
+            ("full_name".to_owned(), "TEXT".to_owned()),
+            ("age".to_owned(), "REAL".to_owned()),
+        ]);
+        let header: String = tx.query_row("SELECT value FROM meta WHERE name = 'header:n'", [], |row| row.get(0)).unwrap();
+        assert_eq!(header, r#"[["id:int","id"],["name","full_name"]]"#);
+    }
+
+    #[test]
+    fn test_create_table_rename_collision() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        let schema = Schema { renames: vec![("a".to_owned(), "B".to_owned())], types: vec![] };
+        let config = Config { schema, ..config() };
+
+        let result = create_table(&tx, "n", &["a", "b"], &mut Type::new(2), &config);
+        assert!(matches!(result, Err(AppError::NameCollision(ref name)) if name == "B"));
+    }
 }
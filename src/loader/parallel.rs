@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, mpsc};
+use std::thread;
+
+use crate::errors::{AppResult, AppResultU};
+
+
+
+/// Approximate size of a chunk in bytes
+const CHUNK_SIZE: usize = 1 << 20;
+
+
+/// A part of the source, starting at `line`
+pub struct Chunk<'a> {
+    /// Byte offset of the end in the source
+    pub end: usize,
+    pub line: usize,
+    pub text: &'a str,
+}
+
+
+impl<'a> Chunk<'a> {
+    /// Lines with their line numbers
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &'a str)> + '_ {
+        self.text.lines().enumerate().map(move |(index, it)| (self.line + index, it))
+    }
+}
+
+
+/// Split the source at the ends of the lines after which `splittable` returns true.
+/// `splittable` is called for every line in order.
+pub fn chunks<F: FnMut(&str) -> bool>(source: &str, mut splittable: F) -> Vec<Chunk<'_>> {
+    let mut result = vec![];
+    let (mut start, mut end, mut line, mut lines) = (0, 0, 1, 0);
+
+    for it in source.split_inclusive('\n') {
+        end += it.len();
+        lines += 1;
+        if splittable(it) && CHUNK_SIZE <= end - start {
+            result.push(Chunk { end, line, text: &source[start .. end] });
+            start = end;
+            line += lines;
+            lines = 0;
+        }
+    }
+    if start < source.len() {
+        result.push(Chunk { end: source.len(), line, text: &source[start ..] });
+    }
+
+    result
+}
+
+/// Split the source at the lines
+pub fn line_chunks(source: &str) -> Vec<Chunk<'_>> {
+    chunks(source, |_| true)
+}
+
+/// Parse the chunks on `jobs` worker threads, and `write` the results in order on this thread.
+/// The workers parse at most `2 * jobs` chunks ahead of the written ones, to bound the parsed results waiting in memory.
+pub fn run<'a, T, P, W>(chunks: &[Chunk<'a>], jobs: usize, parse: P, mut write: W) -> AppResultU
+where T: Send, P: Fn(&Chunk<'a>) -> AppResult<T> + Sync, W: FnMut(&Chunk<'a>, T) -> AppResultU {
+    if jobs <= 1 || chunks.len() <= 1 {
+        for chunk in chunks {
+            write(chunk, parse(chunk)?)?;
+        }
+        return Ok(());
+    }
+
+    let window = jobs * 2;
+    let next = AtomicUsize::new(0);
+    // The number of the written chunks, and whether the writer has stopped
+    let written = (Mutex::new((0, false)), Condvar::new());
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(window);
+        for _ in 0 .. jobs.min(chunks.len()) {
+            let (sender, next, written, parse) = (sender.clone(), &next, &written, &parse);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if chunks.len() <= index {
+                    break;
+                }
+                {
+                    let (lock, condvar) = written;
+                    let mut state = lock.lock().expect("BUG");
+                    while state.0 + window <= index && !state.1 {
+                        state = condvar.wait(state).expect("BUG");
+                    }
+                    if state.1 {
+                        break;
+                    }
+                }
+                // The receiver is dropped when the writer fails
+                if sender.send((index, parse(&chunks[index]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let result = (|| {
+            let mut pending = BTreeMap::new();
+            let mut expected = 0;
+            for (index, parsed) in receiver {
+                pending.insert(index, parsed);
+                while let Some(parsed) = pending.remove(&expected) {
+                    write(&chunks[expected], parsed?)?;
+                    expected += 1;
+                    let (lock, condvar) = &written;
+                    lock.lock().expect("BUG").0 = expected;
+                    condvar.notify_all();
+                }
+            }
+            Ok(())
+        })();
+
+        let (lock, condvar) = &written;
+        lock.lock().expect("BUG").1 = true;
+        condvar.notify_all();
+        result
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::errors::AppError;
+    use super::*;
+
+    fn chunks(n: usize) -> Vec<Chunk<'static>> {
+        (0 .. n).map(|index| Chunk { end: index, line: index + 1, text: "" }).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let chunks = chunks(50);
+        let mut written = vec![];
+        run(&chunks, 4, |chunk| {
+            thread::sleep(Duration::from_millis((chunk.end % 3) as u64));
+            Ok(chunk.line)
+        }, |chunk, line| {
+            assert_eq!(chunk.line, line);
+            written.push(line);
+            Ok(())
+        }).unwrap();
+        assert_eq!(written, (1 ..= 50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parse_ahead() {
+        let chunks = chunks(100);
+        let parsed = AtomicUsize::new(0);
+        let mut ahead = 0;
+        run(&chunks, 2, |chunk| {
+            if chunk.end == 0 {
+                thread::sleep(Duration::from_millis(100));
+            }
+            parsed.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }, |chunk, _| {
+            if chunk.end == 0 {
+                ahead = parsed.load(Ordering::SeqCst);
+            }
+            Ok(())
+        }).unwrap();
+        assert!(ahead <= 4, "{} chunks parsed", ahead);
+        assert_eq!(parsed.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn test_errors() {
+        let chunks = chunks(100);
+        let result = run(&chunks, 4, |chunk| if chunk.end == 30 { Err(AppError::Fixed("parse")) } else { Ok(()) }, |chunk, _| {
+            assert!(chunk.end < 30);
+            Ok(())
+        });
+        assert!(matches!(result, Err(AppError::Fixed("parse"))));
+
+        let result = run(&chunks, 4, |_| Ok(()), |chunk, _| if chunk.end == 10 { Err(AppError::Fixed("write")) } else { Ok(()) });
+        assert!(matches!(result, Err(AppError::Fixed("write"))));
+    }
+}
@@ -1,60 +1,80 @@
 
 use regex::Regex;
 use rusqlite:: Transaction;
-use rusqlite::types::ToSql;
 
-use crate::db::TxExt;
-use crate::errors::{AppError, AppResultU};
+use crate::errors::{AppError, AppResult, AppResultU};
 use crate::types::Type;
 use crate::ui;
+use super::bulk::{self, Inserter};
+use super::parallel::{self, Chunk};
 
 
 
+/// Line number, line, and the captured fields if matched
+type Row<'a> = (usize, &'a str, Option<Vec<&'a str>>);
+
+
 pub struct Loader {
     pub format: Regex,
 }
 
 
 impl super::Loader for Loader {
-    fn load(&self, tx: &Transaction, source: &str, _: &super::Config) -> AppResultU {
-        self.insert_rows(tx, &source)?;
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        self.insert_rows(tx, source, config)?;
         Ok(())
     }
 }
 
 impl Loader {
-    fn insert_rows(&self, tx: &Transaction, content: &str) -> AppResultU {
-        let mut p = ui::Progress::new();
-
-        let mut header = None;
-        let mut insert = None;
-
-        for row in content.lines() {
-            p.progress();
-
-            if let Some(matches) = self.format.captures(row) {
-                if header.is_none() {
-                    let h = super::alpha_header(matches.len() - 1);
-                    let types = Type::new(h.len());
-                    tx.create_table(&types, h.as_slice())?;
-                    insert = Some(super::insert_values(h.len()));
-                    header = Some(h);
-                }
+    fn insert_rows(&self, tx: &Transaction, content: &str, config: &super::Config) -> AppResultU {
+        let mut p = ui::Progress::with_total("load", content.len());
+
+        let mut inserter = Inserter::new(tx, "n");
+        let mut columns = None;
+
+        let chunks = parallel::line_chunks(content);
+        parallel::run(&chunks, config.jobs, |chunk| self.parse_chunk(chunk), |chunk, rows| {
+            for (line, row, fields) in rows {
+                p.progress();
 
-                let mut values = Vec::<&str>::new();
-                for i in 1 ..= header.as_ref().expect("BUG").len() {
-                    values.push(matches.get(i).ok_or(AppError::FewColumns)?.as_str());
+                let fields = match fields {
+                    Some(fields) => fields,
+                    None => {
+                        eprintln!("Skip: {}", row);
+                        continue;
+                    },
+                };
+
+                if columns.is_none() {
+                    let mut types = Type::new(fields.len());
+                    let h = super::create_table(tx, "n", &super::positional_header(fields.len(), config), &mut types, config)?;
+                    columns = Some(Some("rowid".to_owned()).into_iter().chain(h).collect::<Vec<String>>());
                 }
-                let args: Vec<&dyn ToSql> = values.iter().map(|it| it as &dyn ToSql).collect();
-                tx.execute(insert.as_ref().expect("BUG"), &*args)?;
-            } else {
-                eprintln!("Skip: {}", row);
+
+                let columns: Vec<&str> = columns.as_ref().expect("BUG").iter().map(AsRef::as_ref).collect();
+                let values = Some(bulk::line(line)).into_iter().chain(fields.into_iter().map(|it| bulk::text(Some(it)))).collect();
+                inserter.insert(&columns, values)?;
             }
-        }
+            p.read(chunk.end);
+            Ok(())
+        })?;
+        inserter.finish()?;
 
         p.complete();
 
         Ok(())
     }
 
+    fn parse_chunk<'a>(&self, chunk: &Chunk<'a>) -> AppResult<Vec<Row<'a>>> {
+        let mut result = vec![];
+        for (line, row) in chunk.lines() {
+            let fields = match self.format.captures(row) {
+                Some(matches) => Some((1 .. matches.len()).map(|i| matches.get(i).map(|it| it.as_str()).ok_or(AppError::FewColumns)).collect::<AppResult<Vec<&str>>>()?),
+                None => None,
+            };
+            result.push((line, row, fields));
+        }
+        Ok(result)
+    }
 }
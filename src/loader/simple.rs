@@ -1,14 +1,12 @@
 
-extern crate quick_csv;
-
 use regex::Regex;
 use rusqlite:: Transaction;
-use rusqlite::types::ToSql;
+use rusqlite::types::Value;
 
-use crate::db::TxExt;
 use crate::errors::{AppError, AppResult, AppResultU};
 use crate::types::Type;
 use crate::ui;
+use super::bulk::{self, Inserter};
 
 
 
@@ -19,49 +17,46 @@ pub struct Loader {
 
 impl super::Loader for Loader {
     fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
-        let header = self.header(&source, config.no_header)?;
-        let types = Type::new(header.len());
-        tx.create_table(&types, header.as_slice())?;
-        self.insert_rows(tx, header.len(), &source)?;
+        let header = self.header(source, config)?;
+        let mut types = Type::new(header.len());
+        let header = super::create_table(tx, "n", &header, &mut types, config)?;
+        let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+        self.insert_rows(tx, header.as_slice(), source, config.no_header)?;
         Ok(())
     }
 }
 
 impl Loader {
-    fn header<'a>(&self, rows: &'a str, no_header: bool) -> AppResult<Vec<&'a str>> {
+    fn header(&self, rows: &str, config: &super::Config) -> AppResult<Vec<String>> {
         let line = rows.lines().next().ok_or(AppError::Fixed("No lines"))?;
         let columns = self.split(line, None);
-        if no_header {
-            Ok(super::alpha_header(columns.len()))
+        if config.no_header {
+            Ok(super::positional_header(columns.len(), config))
         } else {
-            Ok(columns)
+            Ok(columns.into_iter().map(str::to_owned).collect())
         }
     }
 
-    fn insert_rows(&self, tx: &Transaction, headers: usize, rows: &str) -> AppResultU {
-        let insert = {
-            let mut insert = "INSERT INTO n VALUES(".to_owned();
-            let mut first = true;
-            for _ in 0..headers {
-                if first {
-                    insert.push_str("?");
-                    first = false;
-                } else {
-                    insert.push_str(",?");
-                }
-            }
-            insert.push_str(")");
-            insert
-        };
+    fn insert_rows(&self, tx: &Transaction, header: &[&str], rows: &str, no_header: bool) -> AppResultU {
+        let columns = bulk::with_rowid(header);
+        let mut inserter = Inserter::new(tx, "n");
 
-        let mut p = ui::Progress::new();
-        let mut stmt = tx.prepare(&insert)?;
-        for row in rows.lines().skip(1) {
+        let mut p = ui::Progress::with_total("load", rows.len());
+        let mut read = 0;
+        for (index, row) in rows.lines().enumerate().skip(if no_header { 0 } else { 1 }) {
+            read += row.len() + 1;
+            p.read(read);
+            if row.trim().is_empty() {
+                continue;
+            }
             p.progress();
-            let row: Vec<&str> = self.split(row, Some(headers));
-            let row: Vec<&dyn ToSql> = row.iter().map(|it| it as &dyn ToSql).collect();
-            stmt.execute(row.as_slice())?;
+            let line = index + 1;
+            // The short rows are padded with NULL
+            let mut row: Vec<Value> = Some(bulk::line(line)).into_iter().chain(self.split(row, Some(header.len())).into_iter().map(|it| bulk::text(Some(it)))).collect();
+            row.resize(columns.len(), Value::Null);
+            inserter.insert(&columns, row)?;
         }
+        inserter.finish()?;
         p.complete();
 
         Ok(())
@@ -0,0 +1,104 @@
+use std::fs::File;
+
+use calamine::{Data, Range, Reader, open_workbook_auto_from_rs};
+use rusqlite:: Transaction;
+use rusqlite::types::Value;
+
+use crate::errors::{AppError, AppResultU};
+use crate::types::Type;
+use crate::ui;
+use super::bulk::Inserter;
+
+
+pub struct Loader {
+    pub sheet: Option<String>,
+}
+
+
+impl super::BinaryLoader for Loader {
+    fn load(&self, tx: &Transaction, source: &File, config: &super::Config) -> AppResultU {
+        let mut workbook = open_workbook_auto_from_rs(source)?;
+
+        if let Some(ref sheet) = self.sheet {
+            let range = workbook.worksheet_range(sheet)?;
+            return load_sheet(tx, "n", &range, config);
+        }
+
+        for (index, name) in workbook.sheet_names().iter().enumerate() {
+            let range = workbook.worksheet_range(name)?;
+            if index == 0 {
+                load_sheet(tx, "n", &range, config)?;
+            } else if range.is_empty() || super::is_reserved_table(name) {
+                eprintln!("Skip sheet: {}", name);
+            } else {
+                load_sheet(tx, name, &range, config)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+fn load_sheet(tx: &Transaction, table: &str, range: &Range<Data>, config: &super::Config) -> AppResultU {
+    let mut rows = range.rows();
+    let width = range.width();
+
+    let header: Vec<String> = if config.no_header {
+        super::positional_header(width, config)
+    } else {
+        let header = rows.next().ok_or(AppError::Fixed("Empty sheet"))?;
+        header.iter().map(|it| it.to_string()).collect()
+    };
+
+    let rows: Vec<&[Data]> = rows.collect();
+    let mut types: Vec<Type> = (0 .. width).map(|index| column_type(rows.iter().map(|row| &row[index]))).collect();
+    let header = super::create_table(tx, table, &header, &mut types, config)?;
+
+    let mut p = ui::Progress::new("load");
+    let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+    let mut inserter = Inserter::new(tx, table);
+    for row in rows {
+        p.progress();
+        let row: Vec<Value> = row.iter().zip(types.iter()).map(|(cell, t)| to_value(cell, t)).collect();
+        inserter.insert(&header, row)?;
+    }
+    inserter.finish()?;
+    p.complete();
+
+    Ok(())
+}
+
+/// Decide the column type from the cell types, not from the text
+fn column_type<'a, I: Iterator<Item = &'a Data>>(cells: I) -> Type {
+    let mut result = None;
+
+    for cell in cells {
+        let t = match *cell {
+            Data::Empty | Data::Error(_) => continue,
+            Data::Int(_) | Data::Bool(_) => Type::Int,
+            Data::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => Type::Int,
+            Data::Float(_) => Type::Real,
+            _ => Type::Text,
+        };
+        result = Some(match (result, t) {
+            (Some(Type::Text), _) | (_, Type::Text) => Type::Text,
+            (Some(Type::Real), _) | (_, Type::Real) => Type::Real,
+            _ => Type::Int,
+        });
+    }
+
+    result.unwrap_or(Type::Text)
+}
+
+fn to_value(cell: &Data, t: &Type) -> Value {
+    match *cell {
+        Data::Int(v) => Value::Integer(v),
+        Data::Float(v) if *t == Type::Int => Value::Integer(v as i64),
+        Data::Float(v) => Value::Real(v),
+        Data::String(ref v) | Data::DateTimeIso(ref v) | Data::DurationIso(ref v) => Value::Text(v.to_owned()),
+        Data::Bool(v) => Value::Integer(v.into()),
+        Data::DateTime(ref v) => Value::Text(v.as_datetime().map_or_else(|| v.as_f64().to_string(), |it| it.to_string())),
+        Data::Error(_) | Data::Empty => Value::Null,
+    }
+}
@@ -0,0 +1,71 @@
+use rusqlite:: Transaction;
+
+use crate::errors::{AppError, AppResultU};
+use crate::types::Type;
+use crate::ui;
+use super::bulk::{self, Inserter};
+
+
+
+/// Rows of cells
+pub type Table = Vec<Vec<String>>;
+
+
+/// Load the `index`th (1-based) table into `n`,
+/// or every table: the first into `n` and the others into `n2`, `n3`...
+pub fn load_tables(tx: &Transaction, tables: &[Table], index: Option<usize>, config: &super::Config) -> AppResultU {
+    if let Some(index) = index {
+        let table = index.checked_sub(1).and_then(|it| tables.get(it)).ok_or(AppError::Fixed("Table not found"))?;
+        return load_table(tx, "n", table, config);
+    }
+
+    if tables.is_empty() {
+        return Err(AppError::Fixed("No tables"));
+    }
+
+    for (index, table) in tables.iter().enumerate() {
+        let name = if index == 0 { "n".to_owned() } else { format!("n{}", index + 1) };
+        load_table(tx, &name, table, config)?;
+    }
+
+    Ok(())
+}
+
+fn load_table(tx: &Transaction, name: &str, table: &Table, config: &super::Config) -> AppResultU {
+    let mut rows = table.iter();
+
+    let header: Vec<String> = if config.no_header {
+        let width = table.iter().map(Vec::len).max().unwrap_or(0);
+        super::positional_header(width, config)
+    } else {
+        let header = rows.next().ok_or(AppError::Fixed("Header not found"))?;
+        header.clone()
+    };
+    let rows: Vec<&Vec<String>> = rows.collect();
+
+    let mut types = Type::new(header.len());
+    if let Some(lines) = config.guess_lines {
+        let guesser = super::TypeGuesser::new()?;
+        types.iter_mut().for_each(|it| *it = Type::Int);
+        for row in rows.iter().take(lines) {
+            for (t, cell) in types.iter_mut().zip(row.iter()) {
+                guesser.update(t, cell);
+            }
+        }
+    }
+
+    let header = super::create_table(tx, name, &header, &mut types, config)?;
+
+    let mut p = ui::Progress::new("load");
+    let header: Vec<&str> = header.iter().map(AsRef::as_ref).collect();
+    let mut inserter = Inserter::new(tx, name);
+    for row in rows {
+        p.progress();
+        let row = (0 .. header.len()).map(|index| bulk::text(row.get(index).cloned())).collect();
+        inserter.insert(&header, row)?;
+    }
+    inserter.finish()?;
+    p.complete();
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use rusqlite:: Transaction;
+use serde_json::{Number, Value};
+
+use crate::errors::{AppError, AppResult, AppResultU};
+use crate::types::Flatten;
+
+use super::json;
+
+
+
+pub struct Loader {
+    pub flatten: Flatten,
+    pub table: Option<String>,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let records = records(source, &self.table)?;
+        let stream = || records.iter().cloned().enumerate().map(|(index, it)| Ok((index + 1, it)));
+        json::load_values(tx, stream, config, &self.flatten)
+    }
+}
+
+
+/// Find the array of tables named by `table` (dotted path),
+/// or the only top-level one.
+fn records(source: &str, table: &Option<String>) -> AppResult<Vec<Value>> {
+    let root: ::toml::Table = source.parse()?;
+    let root = ::toml::Value::Table(root);
+
+    let array = if let Some(ref table) = *table {
+        let mut value = &root;
+        for key in table.split('.') {
+            value = value.get(key).ok_or("TOML array of tables not found")?;
+        }
+        value.as_array().filter(|it| is_array_of_tables(it)).ok_or("TOML array of tables not found")?
+    } else {
+        let mut found = root.as_table().expect("BUG").values().filter_map(|it| it.as_array().filter(|it| is_array_of_tables(it)));
+        let array = found.next().ok_or("No TOML array of tables")?;
+        if found.next().is_some() {
+            return Err(AppError::Fixed("Multiple TOML arrays of tables: specify --toml-table"));
+        }
+        array
+    };
+
+    Ok(array.iter().map(to_json).collect())
+}
+
+fn is_array_of_tables(array: &[::toml::Value]) -> bool {
+    !array.is_empty() && array.iter().all(::toml::Value::is_table)
+}
+
+fn to_json(value: &::toml::Value) -> Value {
+    use ::toml::Value::*;
+
+    match *value {
+        String(ref v) => Value::String(v.to_owned()),
+        Integer(v) => Value::Number(v.into()),
+        Float(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        Boolean(v) => Value::Bool(v),
+        Datetime(ref v) => Value::String(v.to_string()),
+        Array(ref v) => Value::Array(v.iter().map(to_json).collect()),
+        Table(ref v) => Value::Object(v.iter().map(|(k, v)| (k.to_owned(), to_json(v))).collect()),
+    }
+}
@@ -0,0 +1,103 @@
+use rusqlite:: Transaction;
+use roxmltree::{Document, Node};
+use serde_json::{Map, Value};
+
+use crate::errors::{AppResult, AppResultU};
+use crate::types::Flatten;
+
+use super::json;
+
+
+
+const TEXT_NAME: &str = "text";
+
+
+pub struct Loader {
+    pub flatten: Flatten,
+    pub record: String,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let records = self.records(source)?;
+        let stream = || records.iter().cloned().enumerate().map(|(index, it)| Ok((index + 1, it)));
+        json::load_values(tx, stream, config, &self.flatten)
+    }
+}
+
+impl Loader {
+    fn records(&self, source: &str) -> AppResult<Vec<Value>> {
+        let document = Document::parse(source)?;
+        let records = document.descendants()
+            .filter(|it| it.is_element() && it.tag_name().name() == self.record)
+            .map(to_object)
+            .collect();
+        Ok(records)
+    }
+}
+
+
+/// Child elements, attributes and the text become keys, suffixed as `NAME_2` .. if already used.
+/// Repeated child elements are stored as JSON text.
+fn to_object(node: Node) -> Value {
+    let mut object = Map::<String, Value>::new();
+    let mut repeated = Map::<String, Value>::new();
+
+    for child in node.children().filter(Node::is_element) {
+        let name = child.tag_name().name().to_owned();
+        let value = to_value(child);
+        if let Some(Value::Array(values)) = repeated.get_mut(&name) {
+            values.push(value);
+        } else if let Some(first) = object.remove(&name) {
+            repeated.insert(name, Value::Array(vec![first, value]));
+        } else {
+            object.insert(name, value);
+        }
+    }
+
+    for (name, values) in repeated {
+        object.insert(name, Value::String(values.to_string()));
+    }
+
+    for attribute in node.attributes() {
+        let name = unique_key(&object, attribute.name());
+        object.insert(name, Value::String(attribute.value().to_owned()));
+    }
+
+    if let Some(text) = text(node) {
+        let name = unique_key(&object, TEXT_NAME);
+        object.insert(name, Value::String(text));
+    }
+
+    Value::Object(object)
+}
+
+fn to_value(node: Node) -> Value {
+    let is_leaf = node.attributes().len() == 0 && !node.children().any(|it| it.is_element());
+    if is_leaf {
+        Value::String(text(node).unwrap_or_default())
+    } else {
+        to_object(node)
+    }
+}
+
+fn unique_key(object: &Map<String, Value>, name: &str) -> String {
+    let mut result = name.to_owned();
+    let mut n = 1;
+    while object.contains_key(&result) {
+        n += 1;
+        result = format!("{}_{}", name, n);
+    }
+    result
+}
+
+/// Text nodes directly under the node
+fn text(node: Node) -> Option<String> {
+    let text: String = node.children().filter(Node::is_text).filter_map(|it| it.text()).filter(|it| !it.trim().is_empty()).collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
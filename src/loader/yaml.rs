@@ -0,0 +1,39 @@
+use rusqlite:: Transaction;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::errors::{AppResult, AppResultU};
+use crate::types::Flatten;
+
+use super::json;
+
+
+
+pub struct Loader {
+    pub flatten: Flatten,
+}
+
+
+impl super::Loader for Loader {
+    fn load(&self, tx: &Transaction, source: &str, config: &super::Config) -> AppResultU {
+        let records = records(source)?;
+        let stream = || records.iter().cloned().enumerate().map(|(index, it)| Ok((index + 1, it)));
+        json::load_values(tx, stream, config, &self.flatten)
+    }
+}
+
+
+/// Each document is a record, or a sequence of records
+fn records(source: &str) -> AppResult<Vec<Value>> {
+    let mut result = vec![];
+
+    for document in serde_yaml::Deserializer::from_str(source) {
+        match Value::deserialize(document)? {
+            Value::Array(values) => result.extend(values),
+            Value::Null => (),
+            value => result.push(value),
+        }
+    }
+
+    Ok(result)
+}
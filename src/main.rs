@@ -4,23 +4,28 @@ extern crate docopt;
 extern crate encoding;
 extern crate serde_json;
 extern crate mktemp;
-extern crate quick_csv;
 extern crate regex;
 extern crate rusqlite;
 
 use std::env;
 use std::fs;
+use std::io::Read;
+use std::path::Path;
 use std::os::unix::process::CommandExt;
 use std::process::{exit, Command};
 
 use rusqlite::Connection;
 
 mod app_options;
+mod archive;
 mod cache;
 mod db;
 mod errors;
+mod files;
 mod loader;
+mod report;
 mod sql;
+mod text;
 mod types;
 mod ui;
 
@@ -46,49 +51,139 @@ fn app() -> AppResultU {
         exit(0);
     }
 
+    ui::set_quiet(options.flag_quiet);
+
     let input = parse_input(&options.arg_csv);
     let source = make_sqlite(&input, &options.flag_c)?;
     let format = options.format();
+    let attachments = options.flag_attach.iter().map(|it| parse_attachment(it)).collect::<Vec<_>>();
+
+    if let Source::Database(ref path) = source {
+        eprintln!("database: {}", path);
+        exec_sqlite(&source, &attachments, &options.flag_q, &options.arg_sqlite_options);
+        return Ok(());
+    }
 
+    // Opening creates the file. A new cache is removed on failure, so it does not need to survive crashes.
+    let new_cache = fs::metadata(source.as_ref()).map_or(true, |it| it.len() == 0);
     let mut conn = Connection::open(source.as_ref())?;
-    let tx = conn.transaction()?;
 
-    let cache = Cache::new(&source, tx);
-    let cache_state = cache.state(&input, &format)?;
-    let config = loader::Config { no_header: options.flag_n, guess_lines: options.flag_g };
+    let columns = |it: &String| it.split(',').map(|it| it.trim().to_owned()).collect::<Vec<_>>();
+    let mut config = loader::Config {
+        columns: options.flag_columns.as_ref().map(columns),
+        filter: options.flag_where.clone(),
+        fts: options.flag_fts.as_ref().map(columns),
+        indexes: options.flag_index.iter().map(columns).collect(),
+        jobs: options.jobs(),
+        no_header: options.flag_n,
+        primary_key: options.flag_primary_key.as_ref().map(columns),
+        guess_lines: options.flag_g,
+        member_tables: options.flag_member_tables,
+        naming: options.flag_naming,
+        schema: options.schema()?,
+        snake_case: options.flag_snake_case,
+    };
+    let (cache_state, reindex) = {
+        let cache = Cache::new(&source, conn.transaction()?);
+        cache.reuse_indexing(&mut config)?;
+        let state = cache.state(&input, &format, &config)?;
+        (state, state.is_fresh() && cache.is_indexing_changed(&config)?)
+    };
 
     if let Some(path) = source.as_ref().to_str() {
         eprintln!("cache: {}", path);
     }
 
-    if options.flag_R || !cache_state.is_fresh() {
-        match cache.refresh(&format, &input, &config,  &options.flag_e) {
-            Ok(_) => (),
-            err => {
-                if cache_state == cache::State::Nothing {
+    if new_cache {
+        conn.execute_batch("PRAGMA synchronous = OFF; PRAGMA journal_mode = MEMORY;")?;
+    }
+
+    let report = if options.flag_R || !cache_state.is_fresh() {
+        let incremental = !options.flag_R && cache_state == cache::State::Changed;
+        match Cache::new(&source, conn.transaction()?).refresh(&format, &input, &config, &options.decoding(), incremental) {
+            Ok(report) => report.to_json()?,
+            Err(err) => {
+                if new_cache {
                     source.remove_file()?;
                 }
-                return err;
+                return Err(err);
             }
         }
+    } else if reindex {
+        Cache::new(&source, conn.transaction()?).reindex(&config)?
+    } else {
+        Cache::new(&source, conn.transaction()?).meta("report")?
+    };
+
+    if new_cache {
+        conn.execute_batch("PRAGMA synchronous = FULL; PRAGMA journal_mode = DELETE;")?;
+    }
+
+    if options.flag_report == Some(ReportFormat::Json) && !report.is_empty() {
+        eprintln!("{}", report);
     }
 
-    exec_sqlite(&source, &options.flag_q, &options.arg_sqlite_options);
+    exec_sqlite(&source, &attachments, &options.flag_q, &options.arg_sqlite_options);
 
     Ok(())
 }
 
+/// `ARCHIVE!PATTERN` selects archive members.
+/// A directory or a glob pattern selects files.
 fn parse_input(filepath: &str) -> Input {
-    match filepath {
-        "-" => Input::Stdin,
-        _ => Input::File(filepath)
+    if filepath == "-" {
+        return Input::Stdin;
+    }
+
+    for (index, _) in filepath.match_indices('!') {
+        let (path, pattern) = (&filepath[.. index], &filepath[index + 1 ..]);
+        if Path::new(path).is_file() {
+            return Input::Archive(path, pattern);
+        }
+    }
+
+    let path = Path::new(filepath);
+    if path.is_dir() || (!path.exists() && filepath.contains(|it| "*?[".contains(it))) {
+        return Input::Glob(filepath);
     }
+
+    Input::File(filepath)
+}
+
+/// `[NAME=]PATH`, NAME defaults to the file stem
+fn parse_attachment(attachment: &str) -> Attachment {
+    let mut parts = attachment.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(path)) if !name.is_empty() =>
+            Attachment { name: name.to_owned(), path: path.to_owned() },
+        _ => {
+            let stem = Path::new(attachment).file_stem().and_then(|it| it.to_str()).unwrap_or("attached");
+            let name = stem.chars().map(|it| if it.is_alphanumeric() { it } else { '_' }).collect();
+            Attachment { name, path: attachment.to_owned() }
+        }
+    }
+}
+
+fn is_database(path: &str) -> AppResult<bool> {
+    const MAGIC: &[u8] = b"SQLite format 3\0";
+
+    let mut header = vec![];
+    fs::File::open(path)?.take(MAGIC.len() as u64).read_to_end(&mut header)?;
+    Ok(header == MAGIC)
 }
 
 fn make_sqlite(input: &Input, cache_filepath: &Option<String>) -> AppResult<Source> {
     match *input {
         Input::Stdin => Ok(Source::Temp(mktemp::Temp::new_file()?)),
-        Input::File(ref input_path) => {
+        Input::Glob(pattern) => {
+            match *cache_filepath {
+                Some(ref path) => Ok(Source::File(path.clone())),
+                None => Ok(Source::File(files::cache_path(pattern))),
+            }
+        },
+        Input::File(input_path) if fs::metadata(input_path)?.is_file() && is_database(input_path)? =>
+            Ok(Source::Database(input_path.to_owned())),
+        Input::File(ref input_path) | Input::Archive(ref input_path, _) => {
             match *cache_filepath {
                 Some(ref path) => Ok(Source::File(path.clone())),
                 None => {
@@ -106,9 +201,13 @@ fn make_sqlite(input: &Input, cache_filepath: &Option<String>) -> AppResult<Sour
     }
 }
 
-fn exec_sqlite(source: &Source, query: &Option<String>, options: &[String]) {
+fn exec_sqlite(source: &Source, attachments: &[Attachment], query: &Option<String>, options: &[String]) {
     let cmd = env::var("NQ_SQLITE").unwrap_or_else(|_| "sqlite3".to_owned());
     let mut cmd = Command::new(cmd);
+    for attachment in attachments {
+        cmd.arg("-cmd");
+        cmd.arg(format!("ATTACH DATABASE {} AS {}", sql::quote_string(&attachment.path), sql::quote_string(&attachment.name)));
+    }
     cmd.arg(source.as_ref());
     cmd.args(options);
     if let Some(ref query) = *query {
@@ -0,0 +1,86 @@
+use rusqlite::Transaction;
+
+use crate::db::TxExt;
+use crate::errors::AppResult;
+use crate::loader;
+use crate::sql;
+use crate::ui;
+
+
+
+/// Summary of a load, stored in `meta` as `report`
+#[derive(Serialize)]
+pub struct Report {
+    pub cache: Option<String>,
+    /// Comma separated encodings of the text content
+    pub encoding: String,
+    pub format: &'static str,
+    pub phases: Vec<Phase>,
+    /// Rows in `n_rejected`
+    pub rejected: usize,
+    /// Rows in `n`
+    pub rows: usize,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    pub seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct Table {
+    pub name: String,
+    pub rows: usize,
+    pub columns: Vec<Column>,
+}
+
+#[derive(Serialize)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: String,
+}
+
+
+impl Report {
+    /// `tables`: Loaded tables
+    pub fn new(tx: &Transaction, tables: &[&str], cache: Option<String>, format: &'static str, encoding: String) -> AppResult<Self> {
+        let mut result = Report { cache, encoding, format, phases: vec![], rejected: 0, rows: 0, tables: vec![] };
+
+        for (name, elapsed) in ui::phases() {
+            result.phases.push(Phase { name, seconds: elapsed.as_secs_f64() });
+        }
+
+        for name in tables {
+            let columns = tx.columns(name)?;
+            if columns.is_empty() {
+                continue;
+            }
+            let rows = count(tx, name)?;
+            match *name {
+                "n" => result.rows = rows,
+                loader::REJECTED_TABLE => {
+                    result.rejected = rows;
+                    continue;
+                },
+                _ => (),
+            }
+            let columns = columns.into_iter().map(|(name, column_type)| Column { name, column_type }).collect();
+            result.tables.push(Table { name: (*name).to_owned(), rows, columns });
+        }
+
+        Ok(result)
+    }
+
+    pub fn to_json(&self) -> AppResult<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+
+fn count(tx: &Transaction, table: &str) -> AppResult<usize> {
+    let rows: i64 = tx.query_row(&format!("SELECT COUNT(*) FROM {}", sql::quote_identifier(table)), [], |row| row.get(0))?;
+    Ok(rows as usize)
+}
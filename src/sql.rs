@@ -5,3 +5,8 @@ pub fn quote_string(s: &str) -> String {
     let s = s.replace("'", "''");
     format!("'{}'", s)
 }
+
+pub fn quote_identifier(s: &str) -> String {
+    let s = s.replace('"', "\"\"");
+    format!("\"{}\"", s)
+}
@@ -0,0 +1,148 @@
+use encoding::{DecoderTrap, Encoding, EncodingRef};
+use encoding::all::{EUC_JP, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, WINDOWS_31J};
+use encoding::label::encoding_from_whatwg_label;
+
+use crate::errors::{AppError, AppResult};
+use crate::types::{DecodePolicy, Decoding};
+
+
+
+const BOMS: &[(&[u8], EncodingRef)] = &[
+    (b"\xEF\xBB\xBF", UTF_8),
+    (b"\xFF\xFE", UTF_16LE),
+    (b"\xFE\xFF", UTF_16BE),
+];
+
+/// Tried in order when the content is not UTF-8. The last one accepts any bytes.
+const LEGACY_ENCODINGS: &[EncodingRef] = &[WINDOWS_31J, EUC_JP, WINDOWS_1252];
+
+
+/// Decode the content with its encoding name.
+/// A BOM decides the encoding and is stripped, otherwise `decoding.encoding` is used, otherwise it is guessed from the content.
+/// Content mostly valid as UTF-8, or any content under the strict policy, is decoded as UTF-8 with the policy.
+pub fn decode(bin: &[u8], decoding: &Decoding) -> AppResult<(String, &'static str)> {
+    for (bom, bom_encoding) in BOMS {
+        if bin.starts_with(bom) {
+            eprintln!("encoding: {} (BOM)", bom_encoding.name());
+            return decode_with(&bin[bom.len() ..], *bom_encoding, decoding.policy);
+        }
+    }
+
+    if let Some(ref encoding) = decoding.encoding {
+        let encoding = encoding_from_whatwg_label(encoding).ok_or("Invalid encoding name")?;
+        return decode_with(bin, encoding, decoding.policy);
+    }
+
+    if let Ok(text) = std::str::from_utf8(bin) {
+        return Ok((text.to_owned(), UTF_8.name()));
+    }
+
+    let (valid, invalid) = utf8_sequences(bin);
+    if decoding.policy == DecodePolicy::Strict || invalid <= valid {
+        return decode_with(bin, UTF_8, decoding.policy);
+    }
+
+    for encoding in LEGACY_ENCODINGS {
+        if let Ok(text) = encoding.decode(bin, DecoderTrap::Strict) {
+            eprintln!("encoding: {} (guessed)", encoding.name());
+            return Ok((text, encoding.name()));
+        }
+    }
+
+    decode_with(bin, WINDOWS_1252, decoding.policy)
+}
+
+/// Numbers of the valid non-ASCII characters and of the invalid sequences as UTF-8
+fn utf8_sequences(bin: &[u8]) -> (usize, usize) {
+    let (mut valid, mut invalid) = (0, 0);
+    let mut rest = bin;
+
+    loop {
+        let (text, error) = match std::str::from_utf8(rest) {
+            Ok(text) => (text, None),
+            Err(error) => (std::str::from_utf8(&rest[.. error.valid_up_to()]).expect("BUG"), Some(error)),
+        };
+        valid += text.chars().filter(|it| !it.is_ascii()).count();
+        match error {
+            Some(error) => {
+                invalid += 1;
+                rest = &rest[error.valid_up_to() + error.error_len().unwrap_or(rest.len() - error.valid_up_to()) ..];
+            },
+            None => return (valid, invalid),
+        }
+    }
+}
+
+fn decode_with(bin: &[u8], encoding: EncodingRef, policy: DecodePolicy) -> AppResult<(String, &'static str)> {
+    let mut decoder = encoding.raw_decoder();
+    let mut text = String::new();
+    let mut invalid = 0;
+    let mut position = 0;
+
+    loop {
+        let (offset, error) = decoder.raw_feed(&bin[position ..], &mut text);
+        let (offset, upto) = match error {
+            Some(error) => (position + offset, position + error.upto as usize),
+            None => match decoder.raw_finish(&mut text) {
+                Some(_) => (position + offset, bin.len()),
+                None => break,
+            },
+        };
+
+        invalid += 1;
+        match policy {
+            DecodePolicy::Ignore => (),
+            DecodePolicy::Replace => text.push('\u{FFFD}'),
+            DecodePolicy::Strict => {
+                let line = text.matches('\n').count() + 1;
+                return Err(AppError::Decode { encoding: encoding.name(), offset, line });
+            },
+        }
+
+        if bin.len() <= upto {
+            break;
+        }
+        position = upto;
+    }
+
+    if 0 < invalid {
+        match policy {
+            DecodePolicy::Ignore => eprintln!("encoding: {} invalid sequences ignored", invalid),
+            DecodePolicy::Replace => eprintln!("encoding: {} invalid sequences replaced", invalid),
+            DecodePolicy::Strict => (),
+        }
+    }
+
+    Ok((text, encoding.name()))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_with_valid() {
+        for policy in [DecodePolicy::Ignore, DecodePolicy::Replace, DecodePolicy::Strict] {
+            assert_eq!(decode_with("aé\n".as_bytes(), UTF_8, policy).unwrap(), ("aé\n".to_owned(), "utf-8"));
+        }
+    }
+
+    #[test]
+    fn test_decode_with_invalid() {
+        let bin = b"a\nb\xffc\xe3\x81";
+        assert_eq!(decode_with(bin, UTF_8, DecodePolicy::Ignore).unwrap().0, "a\nbc");
+        assert_eq!(decode_with(bin, UTF_8, DecodePolicy::Replace).unwrap().0, "a\nb\u{FFFD}c\u{FFFD}");
+        assert!(matches!(decode_with(bin, UTF_8, DecodePolicy::Strict), Err(AppError::Decode { offset: 3, line: 2, .. })));
+    }
+
+    #[test]
+    fn test_decode_mostly_utf8() {
+        let decoding = |policy| Decoding { encoding: None, policy };
+        let bin = "é\nà\n\u{3042}\n\u{FF}\n".bytes().chain(Some(0x80)).collect::<Vec<u8>>();
+        assert_eq!(decode(&bin, &decoding(DecodePolicy::Replace)).unwrap().1, "utf-8");
+        assert!(matches!(decode(&bin, &decoding(DecodePolicy::Strict)), Err(AppError::Decode { offset: 13, line: 5, .. })));
+        assert_eq!(decode(b"caf\xe9\n", &decoding(DecodePolicy::Replace)).unwrap(), ("café\n".to_owned(), "windows-1252"));
+    }
+}
@@ -12,20 +12,139 @@ pub enum Type {
 
 #[derive(Clone, Debug)]
 pub enum Format {
-    Csv(Option<u8>),
-    Json,
+    Columnar,
+    Csv(Dialect),
+    FixedWidth(Option<String>),
+    Html(Option<usize>),
+    Json(Flatten),
+    Logfmt,
     Ltsv,
+    Markdown(Option<usize>),
     Regex(String),
     Simple,
+    Spreadsheet(Option<String>),
+    Toml(Flatten, Option<String>),
+    Xml(Flatten, String),
+    Yaml(Flatten),
+}
+
+/// CSV dialect
+#[derive(Clone, Debug)]
+pub struct Dialect {
+    pub delimiter: char,
+    pub quote: char,
+    /// Escapes the next character in quoted fields, in addition to the doubled quote
+    pub escape: Option<char>,
+    /// Lines starting with it are skipped
+    pub comment: Option<String>,
+    pub trim: bool,
+    /// 1-based line number of the header (or of the first row without header)
+    pub header_line: usize,
+    /// Number of the trailing lines to skip
+    pub skip_footer: usize,
+    pub ragged: Ragged,
+}
+
+/// How to load the rows with fewer or more fields than the header
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Ragged {
+    /// Pad the short rows with NULL, and join the extra fields into the `overflow` column
+    Overflow,
+    /// Pad the short rows with NULL
+    Pad,
+    /// Move the rows into the `n_rejected` table
+    Reject,
+    Strict,
+    /// Pad the short rows with NULL, and drop the extra fields
+    Truncate,
+}
+
+/// Column types and names overriding the loaded ones
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    /// Old and new names
+    pub renames: Vec<(String, String)>,
+    pub types: Vec<(String, Type)>,
 }
 
+/// Names of the columns without header
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Naming {
+    /// `a` .. `z`, `aa`, `ab` ..
+    Alpha,
+    /// `c1`, `c2` ..
+    Numbered,
+}
+
+/// How to decode the invalid bytes
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum DecodePolicy {
+    Ignore,
+    Replace,
+    Strict,
+}
+
+/// Format of the load report
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ReportFormat {
+    Json,
+}
+
+pub struct Decoding {
+    /// Guessed if `None`
+    pub encoding: Option<String>,
+    pub policy: DecodePolicy,
+}
+
+/// A database attached to the sqlite3 session as `name`
+pub struct Attachment {
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Flatten {
+    pub delimiter: String,
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug)]
 pub enum Input<'a> {
+    /// Archive path and member pattern
+    Archive(&'a str, &'a str),
     File(&'a str),
+    /// Directory or glob pattern
+    Glob(&'a str),
     Stdin,
 }
 
 
 impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Columnar => "columnar",
+            Format::Csv(_) => "csv",
+            Format::FixedWidth(_) => "fixed-width",
+            Format::Html(_) => "html",
+            Format::Json(_) => "json",
+            Format::Logfmt => "logfmt",
+            Format::Ltsv => "ltsv",
+            Format::Markdown(_) => "markdown",
+            Format::Regex(_) => "regex",
+            Format::Simple => "simple",
+            Format::Spreadsheet(_) => "spreadsheet",
+            Format::Toml(..) => "toml",
+            Format::Xml(..) => "xml",
+            Format::Yaml(_) => "yaml",
+        }
+    }
+
+    pub fn to_sql_literal(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl<'a> Input<'a> {
     pub fn to_sql_literal(&self) -> String {
         format!("{:?}", self)
     }
@@ -37,4 +156,14 @@ impl Type {
         types.resize(size, Type::Text);
         types
     }
+
+    /// `int`, `real` or `text` (and their SQL synonyms)
+    pub fn parse(name: &str) -> Option<Type> {
+        match name.trim().to_lowercase().as_str() {
+            "int" | "integer" => Some(Type::Int),
+            "real" | "float" | "double" => Some(Type::Real),
+            "text" | "string" => Some(Type::Text),
+            _ => None,
+        }
+    }
 }
@@ -1,29 +1,137 @@
+use std::io::{IsTerminal, Write, stderr};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 
 
+/// Interval to redraw the progress line on terminals
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// Interval to print the progress summaries when stderr is not a terminal
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Elapsed times by phase, in order of appearance
+static PHASES: Mutex<Vec<(&str, Duration)>> = Mutex::new(vec![]);
+
+
 pub struct Progress {
     pub n: usize,
+    bytes: usize,
+    phase: &'static str,
+    shown: Instant,
+    started: Instant,
+    terminal: bool,
+    total: Option<usize>,
+}
+
+
+/// Silence the progress
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Add the elapsed time to the phase
+pub fn record(phase: &'static str, elapsed: Duration) {
+    let mut phases = PHASES.lock().expect("BUG");
+    match phases.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, total)) => *total += elapsed,
+        None => phases.push((phase, elapsed)),
+    }
+}
+
+pub fn phases() -> Vec<(&'static str, Duration)> {
+    PHASES.lock().expect("BUG").clone()
 }
 
 
 impl Progress {
-    pub fn new() -> Progress {
-        Progress { n: 0 }
+    pub fn new(phase: &'static str) -> Progress {
+        let now = Instant::now();
+        Progress { n: 0, bytes: 0, phase, shown: now, started: now, terminal: stderr().is_terminal(), total: None }
+    }
+
+    /// Progress in reading `total` bytes
+    pub fn with_total(phase: &'static str, total: usize) -> Progress {
+        Progress { total: Some(total), ..Progress::new(phase) }
     }
 
     pub fn progress(&mut self) {
         self.n += 1;
-        self.show(false);
+        self.show();
+    }
+
+    /// The source has been read up to `bytes`
+    pub fn read(&mut self, bytes: usize) {
+        self.bytes = bytes;
+        self.show();
     }
 
     pub fn complete(&self) {
-        self.show(true);
+        let elapsed = self.started.elapsed();
+        record(self.phase, elapsed);
+        if QUIET.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut line = format!("{}: {} rows", self.phase, self.n);
+        if let Some(total) = self.total {
+            line.push_str(&format!(", {}", bytes(total)));
+        }
+        line.push_str(&format!(" in {:.1}s ({} rows/s)", elapsed.as_secs_f64(), self.rate(elapsed)));
+        self.print(&line, true);
     }
 
-    fn show(&self, last: bool) {
-        let just = self.n % 100 == 0;
-        if last ^ just {
-            eprintln!("{} rows", self.n);
+    fn show(&mut self) {
+        let interval = if self.terminal { REDRAW_INTERVAL } else { SUMMARY_INTERVAL };
+        if self.shown.elapsed() < interval || QUIET.load(Ordering::Relaxed) {
+            return;
+        }
+        self.shown = Instant::now();
+
+        let elapsed = self.started.elapsed();
+        let mut line = format!("{}: ", self.phase);
+        if let Some(total) = self.total {
+            let ratio = self.bytes as f64 / total.max(1) as f64;
+            line.push_str(&format!("{} / {} ({:.0}%), ", bytes(self.bytes), bytes(total), ratio * 100.0));
         }
+        line.push_str(&format!("{} rows, {} rows/s", self.n, self.rate(elapsed)));
+        if let Some(total) = self.total.filter(|_| 0 < self.bytes) {
+            let eta = elapsed.as_secs_f64() * (total.saturating_sub(self.bytes)) as f64 / self.bytes as f64;
+            line.push_str(&format!(", ETA {:.0}s", eta));
+        }
+        self.print(&line, false);
+    }
+
+    /// Overwrite the line on terminals
+    fn print(&self, line: &str, last: bool) {
+        let mut err = stderr().lock();
+        let _ = if !self.terminal {
+            writeln!(err, "{}", line)
+        } else if last {
+            writeln!(err, "\r\x1b[K{}", line)
+        } else {
+            write!(err, "\r\x1b[K{}", line)
+        };
+    }
+
+    fn rate(&self, elapsed: Duration) -> usize {
+        (self.n as f64 / elapsed.as_secs_f64().max(0.001)) as usize
+    }
+}
+
+
+fn bytes(n: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while 1024.0 <= value && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", value, UNITS[unit])
 }